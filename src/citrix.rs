@@ -1,21 +1,45 @@
 use crate::{
+    credentials::resolve_credentials,
     extract::{get_attribute_value, get_cookie_value, get_element_value, get_header_attribute},
-    storage::Settings,
+    ica::{application_section, get_value, set_value},
+    io::{detail_and_log, spit_and_log},
+    negotiate::authorization_header,
+    nfactor::{is_login_schema, LoginSchema, LOGIN_SCHEMA_PATH, MAX_FACTORS},
+    storage::{AuthMethod, SessionCleanup, Settings},
 };
 use reqwest::{
     blocking::{self, Client},
     cookie::Jar,
-    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, HOST, ORIGIN, REFERER},
-    Url,
+    header::{
+        HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_LENGTH, HOST, ORIGIN, REFERER,
+    },
+    StatusCode, Url,
 };
 use serde::Deserialize;
 use std::{
+    fmt::{self, Display, Formatter},
     fs::File,
     io::copy,
     str::{from_utf8, FromStr},
     sync::Arc,
 };
-use sysinfo::System;
+
+/// StoreFront Web path which ends the StoreFront session
+const LOGOFF_PATH: &str = "Authentication/Logoff";
+/// Query parameter carrying the device's client name in the StoreFront launch request
+const CLIENT_NAME_PARAMETER: &str = "ClientName";
+/// Query parameter carrying command line arguments in the StoreFront launch request
+const LAUNCH_ARGUMENTS_PARAMETER: &str = "LaunchParams";
+/// Default StoreFront Web path to the user's session list
+const SESSIONS_LIST_PATH: &str = "Sessions/ListAvailable";
+/// Default StoreFront Web path which disconnects the user's sessions
+const SESSIONS_DISCONNECT_PATH: &str = "Sessions/Disconnect";
+/// Default StoreFront Web path which logs off the user's sessions
+const SESSIONS_LOGOFF_PATH: &str = "Sessions/Logoff";
+/// File the ICA file is saved to; the client process is recognized by it
+pub const ICA_FILE: &str = "AutoLaunch.ica";
+/// Gateway path which ends the NetScaler session (classic and nFactor)
+const GATEWAY_LOGOUT_PATH: &str = "/cgi/logout";
 
 /// Simplified header object for Reqwest
 struct ProtoHeader(HeaderName, HeaderValue);
@@ -26,8 +50,8 @@ struct ProtoHeader(HeaderName, HeaderValue);
 #[serde(rename_all = "camelCase")]
 struct ResourceList {
     // is_subscription_enabled: Option<bool>,
-    // is_unauthenticated_user: Option<bool>,
-    resources: Option<Vec<Resource>>, // List of resources from Citrix StoreFront
+    is_unauthenticated_user: Option<bool>, // True when the store hands out resources without logon
+    resources: Option<Vec<Resource>>,      // List of resources from Citrix StoreFront
 }
 
 /// Resource object from Citrix StoreFront
@@ -38,7 +62,7 @@ struct Resource {
     // clienttypes: Option<Vec<String>>,
     // description: Option<String>,
     // iconurl: Option<String>,
-    // id: Option<String>,
+    id: Option<String>, // Resource ID, used to match sessions to resources
     // launchstatusurl: Option<String>,
     // path: Option<String>,
     // shortcutvalidationurl: Option<String>,
@@ -47,6 +71,83 @@ struct Resource {
     name: Option<String>,      // Name of resource as seen in Citrix StoreFront
 }
 
+/// Session object from Citrix StoreFront's session list
+#[derive(Deserialize)]
+pub struct UserSession {
+    #[serde(rename = "appids")]
+    pub app_ids: Option<Vec<String>>, // IDs of the resources running in the session
+    #[serde(rename = "connectionstate")]
+    pub connection_state: Option<String>, // Active or Disconnected
+    #[serde(rename = "initialapp")]
+    pub initial_app: Option<String>, // ID of the resource the session was started for
+    #[serde(rename = "launchurl")]
+    pub launch_url: Option<String>, // Part of URL for reconnect ICA file download
+    #[serde(rename = "servername")]
+    pub server_name: Option<String>, // VDA hosting the session
+    #[serde(rename = "sessionid")]
+    pub session_id: Option<String>, // Session identifier on the VDA
+}
+
+impl UserSession {
+    /// Check if the session is disconnected (running on the VDA without a client)
+    pub fn is_disconnected(&self) -> bool {
+        match &self.connection_state {
+            Some(c) => c.eq_ignore_ascii_case("Disconnected"),
+            None => false,
+        }
+    }
+
+    /// Check if the session has a client connected
+    pub fn is_active(&self) -> bool {
+        match &self.connection_state {
+            Some(c) => c.eq_ignore_ascii_case("Active"),
+            None => false,
+        }
+    }
+
+    /// Check if the session is running the given resource
+    fn runs(&self, resource_id: &str) -> bool {
+        let in_app_ids = match &self.app_ids {
+            Some(a) => a.iter().any(|id| id == resource_id),
+            None => false,
+        };
+        in_app_ids || self.initial_app.as_deref() == Some(resource_id)
+    }
+}
+
+/// Describe a session for the console and log
+/// - Example: "Active session 3 on VDA01 (Controller.Notepad)"
+impl Display for UserSession {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} session {} on {}",
+            self.connection_state.as_deref().unwrap_or("Unknown"),
+            self.session_id.as_deref().unwrap_or("?"),
+            self.server_name.as_deref().unwrap_or("unknown server")
+        )?;
+        if let Some(a) = &self.app_ids {
+            write!(f, " ({})", a.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Logged in StoreFront web session
+/// - Keeps the client and cookies so the session can be used and ended later
+pub struct StoreSession {
+    client: Client,                   // Client holding the session cookies
+    base_url: Url,                    // URL entered by the user (gateway or StoreFront)
+    internal_url: Url,                // StoreFront Web URL behind the gateway
+    csrf_token: String,               // CSRF token required by StoreFront requests
+    custom_headers: Vec<ProtoHeader>, // CSRF and Referer headers for StoreFront requests
+    resources: Vec<Resource>,         // Resources published to the user
+    sessions_list_path: String,       // Path to the user's session list
+    sessions_disconnect_path: String, // Path to disconnect the user's sessions
+    sessions_logoff_path: String,     // Path to log off the user's sessions
+    gateway: bool,                    // True if a gateway session was established
+}
+
 /// Headers used for Citrix StoreFront requests\
 /// **Note: These headers are required for correct StoreFront interaction**
 /// - Includes common headers and optional custom headers
@@ -69,7 +170,7 @@ fn common_headers(
         Ok(h) => h,
         Err(e) => return Err(format!("Failed to create base URI header: {}", e)),
     };
-    let origin_base_uri = match HeaderValue::from_str(&base_uri.to_string()) {
+    let origin_base_uri = match HeaderValue::from_str(base_uri.as_ref()) {
         Ok(h) => h,
         Err(e) => return Err(format!("Failed to create base URI header: {}", e)),
     };
@@ -96,47 +197,48 @@ fn common_headers(
 
 // TODO: Add URL builder function to provide full URLs for below function
 
-/// Check if Citrix Workspace is running
-/// - Uses sysinfo crate to check for wfica32.exe process
-/// - Returns true if process is found, false otherwise
-pub fn ica_is_running() -> bool {
-    let mut system = System::new();
-    system.refresh_all();
-    let processes = system.processes();
-    if processes.iter().any(|(_, p)| p.name() == "wfica32.exe") {
-        true
-    } else {
-        false
+/// Make sure the launch arguments reach the published application
+/// - Apps published to accept parameters get them from StoreFront in LongCommandLine
+/// - Otherwise, LongCommandLine is written into the ICA file directly
+/// - Returns the (possibly updated) ICA file contents
+fn apply_launch_arguments(contents: &str, settings: &Settings) -> String {
+    if settings.launch_arguments.is_empty() {
+        return contents.to_string();
     }
-}
-
-/// Get ICA file from Citrix StoreFront
-/// - Uses Reqwest to interact with Citrix StoreFront
-/// - Requires a Settings object with login and passwd fields
-/// - Returns a Result with the file name on success, error message on failure
-pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
-    let application_name = settings.application_name.clone();
-    let base_url = match Url::parse(&settings.base_uri) {
-        Ok(u) => u,
-        Err(e) => return Err(format!("Failed to parse base URI: {}", e)),
-    };
-    // Client setup
-    let jar = Arc::new(Jar::default());
-    let client = match Client::builder().cookie_provider(Arc::clone(&jar)).build() {
-        Ok(c) => c,
-        Err(e) => return Err(format!("Failed to build client: {}", e)),
-    };
-
-    // Get Initial URL from base URL (usually Logon/LogonPoint)
-    let response = match blocking::get(base_url.clone()) {
-        Ok(r) => r,
-        Err(e) => return Err(format!("Failed to get base URL: {}", e)),
-    };
-    let initial_url = match response.url().join("./") {
-        Ok(u) => u,
-        Err(e) => return Err(format!("Failed to build URI: {}", e)),
+    let section = match application_section(contents) {
+        Some(s) => s,
+        None => {
+            spit_and_log("ICA file names no application. Launch arguments were not applied.");
+            return contents.to_string();
+        }
     };
+    match get_value(contents, &section, "LongCommandLine") {
+        Some(v) if v.contains(&settings.launch_arguments) => contents.to_string(),
+        _ => {
+            spit_and_log(
+                "StoreFront did not pass the launch arguments. Writing them to the ICA file.",
+            );
+            set_value(
+                contents,
+                &section,
+                "LongCommandLine",
+                &settings.launch_arguments,
+            )
+        }
+    }
+}
 
+/// Log in to the gateway in front of StoreFront
+/// - Discovers the gateway's auth methods from the initial (LogonPoint) URL
+/// - Answers either the classic logon form or an nFactor login schema
+/// - Sets the client type once authenticated
+/// - Returns true if a gateway session was established (false for unauthenticated stores)
+fn gateway_logon(
+    client: &Client,
+    base_url: &Url,
+    initial_url: &Url,
+    settings: &Settings,
+) -> Result<bool, String> {
     // Call to Home/Configuration for Resource List path
     // Note that Home/Configuration seems to be a default path for the API
     let uri = match initial_url.join("Home/Configuration") {
@@ -145,10 +247,7 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
     };
     let response = match client
         .post(uri)
-        .headers(match common_headers(None, settings) {
-            Ok(h) => h,
-            Err(e) => return Err(e),
-        })
+        .headers(common_headers(None, settings)?)
         .header(CONTENT_LENGTH, "0")
         .send()
     {
@@ -171,10 +270,7 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
     };
     let response = match client
         .post(uri)
-        .headers(match common_headers(None, settings) {
-            Ok(h) => h,
-            Err(e) => return Err(e),
-        })
+        .headers(common_headers(None, settings)?)
         .header(CONTENT_LENGTH, "0")
         .send()
     {
@@ -182,12 +278,19 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
         Err(e) => return Err(format!("Failed to post resource list: {}", e)),
     };
     let auth_methods_path = match get_header_attribute(
-        &response.headers(),
+        response.headers(),
         "CitrixWebReceiver-Authenticate",
         "location",
     ) {
         Ok(a) => a,
-        Err(e) => return Err(format!("Failed to get auth methods path: {}", e)),
+        Err(e) => {
+            // Unauthenticated stores answer with the resource list instead
+            let response_text = response.text().unwrap_or_default();
+            return match unauthenticated_resources(&response_text) {
+                Some(_) => Ok(false),
+                None => Err(format!("Failed to get auth methods path: {}", e)),
+            };
+        }
     };
 
     // Call to Auth Methods Init for proper auth methods path
@@ -197,10 +300,7 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
     };
     let response = match client
         .post(uri)
-        .headers(match common_headers(None, settings) {
-            Ok(h) => h,
-            Err(e) => return Err(e),
-        })
+        .headers(common_headers(None, settings)?)
         .header(CONTENT_LENGTH, "0")
         .send()
     {
@@ -215,7 +315,14 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
     let auth_methods_proper_path =
         match get_attribute_value(&body, "method name=\"ExplicitForms\"", "url") {
             Ok(a) => a,
-            Err(e) => return Err(format!("Failed to get proper auth methods path: {}", e)),
+            Err(e) => {
+                // nFactor-only gateways may not advertise ExplicitForms
+                spit_and_log(&format!(
+                    "ExplicitForms not advertised ({}), trying nFactor login schema.",
+                    e
+                ));
+                LOGIN_SCHEMA_PATH.to_string()
+            }
         };
 
     // Pull auth methods for state_context
@@ -225,10 +332,7 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
     };
     let response = match client
         .post(uri)
-        .headers(match common_headers(None, settings) {
-            Ok(h) => h,
-            Err(e) => return Err(e),
-        })
+        .headers(common_headers(None, settings)?)
         .send()
     {
         Ok(r) => r,
@@ -238,11 +342,196 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
         Ok(i) => i,
         Err(e) => return Err(format!("Failed to retrieve state context: {}", e)),
     };
-    let state_context = match get_element_value(&input, "StateContext") {
+
+    // Authenticate to gateway for AAAC cookie
+    // - nFactor gateways describe their own fields, classic gateways use a fixed form
+    let (state_context, set_client_path) = match is_login_schema(&input) {
+        true => nfactor_logon(client, base_url, &input, settings)?,
+        false => classic_logon(client, base_url, &input, settings)?,
+    };
+
+    // Set client (useful for who knows what)
+    // TODO: Figure out what this does
+    let set_client_settings = &[
+        ("nsg-setclient", "wica"),
+        ("StateContext", state_context.as_str()),
+    ];
+    let uri = match base_url.join(&set_client_path) {
+        Ok(u) => u,
+        Err(e) => return Err(format!("Failed to build URI: {}", e)),
+    };
+    match client
+        .post(uri)
+        .headers(common_headers(None, settings)?)
+        .form(set_client_settings)
+        .send()
+    {
+        Ok(_) => (),
+        Err(e) => return Err(format!("Failed to set client: {}", e)),
+    };
+    Ok(true)
+}
+
+/// Log in to StoreFront with domain pass-through (Kerberos/Negotiate)
+/// - Sends a SPNEGO token built from the current user's credential cache
+/// - Returns an error if StoreFront does not accept the ticket
+fn negotiate_logon(
+    client: &Client,
+    uri: Url,
+    custom_headers: &Vec<ProtoHeader>,
+    settings: &Settings,
+) -> Result<(), String> {
+    let host = match uri.host_str() {
+        Some(h) => h.to_string(),
+        None => return Err("Failed to parse host for Negotiate login".to_string()),
+    };
+    let authorization = authorization_header(&host)?;
+    let response = match client
+        .post(uri)
+        .headers(common_headers(Some(custom_headers), settings)?)
+        .header(AUTHORIZATION, authorization)
+        .header(CONTENT_LENGTH, "0")
+        .send()
+    {
+        Ok(r) => r,
+        Err(e) => return Err(format!("Failed to log in with Negotiate: {}", e)),
+    };
+    match response.status() {
+        StatusCode::UNAUTHORIZED => Err(format!(
+            "StoreFront rejected the Kerberos ticket for HTTP@{} (check klist and the service principal)",
+            host
+        )),
+        s if s.is_success() => Ok(()),
+        s => Err(format!("Negotiate login failed with status {}", s)),
+    }
+}
+
+/// Log in to StoreFront itself for the CtxsAuthId cookie
+/// - Accepts the auth methods path from the CitrixWebReceiver-Authenticate header
+/// - Uses CitrixAGBasic after a gateway logon, or IntegratedWindows for pass-through
+fn storefront_logon(
+    client: &Client,
+    internal_url: &Url,
+    auth_methods_path: &str,
+    custom_headers: &Vec<ProtoHeader>,
+    settings: &Settings,
+) -> Result<(), String> {
+    // Get auth methods (real) for CitrixAGBasic or IntegratedWindows relative path
+    let uri = match internal_url.join(auth_methods_path) {
+        Ok(u) => u,
+        Err(e) => return Err(format!("Failed to build URI: {}", e)),
+    };
+    let response = match client
+        .post(uri)
+        .headers(common_headers(Some(custom_headers), settings)?)
+        .header(CONTENT_LENGTH, "0")
+        .send()
+    {
+        Ok(r) => r,
+        Err(e) => return Err(format!("Failed to get auth methods: {}", e)),
+    };
+    let input = match response.text() {
+        Ok(i) => i,
+        Err(e) => return Err(format!("Failed to get auth methods: {}", e)),
+    };
+    // TODO: [ISSUE 6] Make this less hacky...
+    let auth_method_name = match settings.auth_method {
+        AuthMethod::ExplicitForms => "CitrixAGBasic",
+        AuthMethod::Negotiate => "IntegratedWindows",
+        AuthMethod::Anonymous => return Ok(()),
+    };
+    let auth_login_path = match get_attribute_value(
+        &input,
+        &format!("method name=\"{}\"", auth_method_name),
+        "url",
+    ) {
+        Ok(a) => a,
+        Err(e) => return Err(format!("Failed to get auth login path: {}", e)),
+    };
+
+    // Log in to get CtxsAuthId cookie
+    let uri = match internal_url.join(&auth_login_path) {
+        Ok(u) => u,
+        Err(e) => return Err(format!("Failed to build URI: {}", e)),
+    };
+    match settings.auth_method {
+        AuthMethod::ExplicitForms => match client
+            .post(uri)
+            .headers(common_headers(Some(custom_headers), settings)?)
+            .header(CONTENT_LENGTH, "0")
+            .send()
+        {
+            Ok(_) => (),
+            Err(e) => return Err(format!("Failed to log in: {}", e)),
+        },
+        AuthMethod::Negotiate => negotiate_logon(client, uri, custom_headers, settings)?,
+        AuthMethod::Anonymous => (),
+    };
+    Ok(())
+}
+
+/// Get the list of resources published to the logged in user
+/// - Returns the resources from the StoreFront resource list
+fn get_resources(
+    client: &Client,
+    internal_url: &Url,
+    resource_list_path: &str,
+    custom_headers: &Vec<ProtoHeader>,
+    settings: &Settings,
+) -> Result<Vec<Resource>, String> {
+    // Get list (should work) to populate ResponseList object
+    let get_list_settings = &[("format", "json"), ("resourceDetails", "Default")];
+    let uri = match internal_url.join(resource_list_path) {
+        Ok(u) => u,
+        Err(e) => return Err(format!("Failed to build URI: {}", e)),
+    };
+    let response = match client
+        .post(uri)
+        .headers(common_headers(Some(custom_headers), settings)?)
+        .form(get_list_settings)
+        .send()
+    {
+        Ok(r) => r,
+        Err(e) => return Err(format!("Failed to get resource list: {}", e)),
+    };
+
+    // Parse response into ResourceList object
+    let response_text = match response.text() {
+        Ok(r) => r,
+        Err(e) => return Err(format!("Failed to get response text: {}", e)),
+    };
+    match serde_json::from_str::<ResourceList>(response_text.as_str()) {
+        Ok(r) => match r.resources {
+            Some(r) => Ok(r),
+            None => Err("No resources found".to_string()),
+        },
+        Err(e) => Err(format!("Error: {:?}", e)),
+    }
+}
+
+/// Get resources from a resource list response if the store is unauthenticated
+/// - Returns None if the response is not a resource list or the store requires a logon
+fn unauthenticated_resources(response_text: &str) -> Option<Vec<Resource>> {
+    match serde_json::from_str::<ResourceList>(response_text) {
+        Ok(r) if r.is_unauthenticated_user == Some(true) => r.resources,
+        _ => None,
+    }
+}
+
+/// Log in to a classic gateway using its fixed logon form
+/// - Accepts the auth requirements response containing StateContext and Postback
+/// - Returns the state context and the path to set the client with
+fn classic_logon(
+    client: &Client,
+    base_url: &Url,
+    input: &str,
+    settings: &Settings,
+) -> Result<(String, String), String> {
+    let state_context = match get_element_value(input, "StateContext") {
         Ok(s) => s.to_string(),
         Err(e) => return Err(format!("Failed to parse state context: {}", e)),
     };
-    let auth_path = match get_element_value(&input, "Postback") {
+    let auth_path = match get_element_value(input, "Postback") {
         Ok(a) => a,
         Err(e) => return Err(format!("Failed to get auth path: {}", e)),
     };
@@ -261,10 +550,7 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
     };
     let response = match client
         .post(uri)
-        .headers(match common_headers(None, settings) {
-            Ok(h) => h,
-            Err(e) => return Err(e),
-        })
+        .headers(common_headers(None, settings)?)
         .form(credentials)
         .send()
     {
@@ -282,29 +568,99 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
             Err(e) => return Err(format!("Failed to get set client path: {}", e)),
         },
     };
+    Ok((state_context, set_client_path))
+}
 
-    // Set client (useful for who knows what)
-    // TODO: Figure out what this does
-    let set_client_settings = &[
-        ("nsg-setclient", "wica"),
-        ("StateContext", state_context.as_str()),
-    ];
-    let uri = match base_url.join(&set_client_path) {
+/// Log in to an nFactor gateway by answering each factor of its LoginSchema
+/// - Accepts the initial LoginSchema body
+/// - Fills every factor from Settings and posts it back until the gateway is satisfied
+/// - Returns the final state context and the redirect path into StoreFront
+fn nfactor_logon(
+    client: &Client,
+    base_url: &Url,
+    input: &str,
+    settings: &Settings,
+) -> Result<(String, String), String> {
+    let mut schema = LoginSchema::parse(input)?;
+    for factor in 1..=MAX_FACTORS {
+        if schema.is_success() {
+            return Ok((schema.state_context, schema.redirect_url));
+        }
+        if schema.is_failure() {
+            return Err(format!(
+                "nFactor authentication failed at factor {}: {}",
+                factor - 1,
+                schema.failure_reason()
+            ));
+        }
+        let credentials = schema.credentials(settings)?;
+        let uri = match base_url.join(&schema.postback) {
+            Ok(u) => u,
+            Err(e) => return Err(format!("Failed to build URI: {}", e)),
+        };
+        let response = match client
+            .post(uri)
+            .headers(common_headers(None, settings)?)
+            .form(&credentials)
+            .send()
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("Failed to authenticate factor {}: {}", factor, e)),
+        };
+        let body = match response.text() {
+            Ok(b) => b,
+            Err(e) => {
+                return Err(format!(
+                    "Failed to retrieve factor {} response: {}",
+                    factor, e
+                ))
+            }
+        };
+        schema = LoginSchema::parse(&body)?;
+    }
+    Err(format!(
+        "nFactor authentication did not complete after {} factors",
+        MAX_FACTORS
+    ))
+}
+
+/// Log in to Citrix StoreFront
+/// - Uses Reqwest to interact with Citrix StoreFront
+/// - Requires a Settings object with login and passwd fields (unless pass-through or unauthenticated)
+/// - Secrets with a credential source are read now, for this logon only
+/// - Returns the logged in session with its resource list, error message on failure
+pub fn logon(settings: &Settings) -> Result<StoreSession, String> {
+    let resolved = resolve_credentials(settings)?;
+    let settings = &resolved;
+    let base_url = match Url::parse(&settings.base_uri) {
+        Ok(u) => u,
+        Err(e) => return Err(format!("Failed to parse base URI: {}", e)),
+    };
+    // Client setup
+    let jar = Arc::new(Jar::default());
+    let client = match Client::builder().cookie_provider(Arc::clone(&jar)).build() {
+        Ok(c) => c,
+        Err(e) => return Err(format!("Failed to build client: {}", e)),
+    };
+
+    // Get Initial URL from base URL (usually Logon/LogonPoint)
+    let response = match blocking::get(base_url.clone()) {
+        Ok(r) => r,
+        Err(e) => return Err(format!("Failed to get base URL: {}", e)),
+    };
+    let initial_url = match response.url().join("./") {
         Ok(u) => u,
         Err(e) => return Err(format!("Failed to build URI: {}", e)),
     };
-    match client
-        .post(uri)
-        .headers(match common_headers(None, settings) {
-            Ok(h) => h,
-            Err(e) => return Err(e),
-        })
-        .form(set_client_settings)
-        .send()
-    {
-        Ok(_) => (),
-        Err(e) => return Err(format!("Failed to set client: {}", e)),
+
+    // Authenticate to the gateway unless StoreFront handles authentication itself
+    let gateway = match settings.auth_method {
+        AuthMethod::ExplicitForms => gateway_logon(&client, &base_url, &initial_url, settings)?,
+        _ => false,
     };
+    if gateway {
+        detail_and_log("Gateway logon succeeded.");
+    }
 
     // Get base_rui redirect for internal path
     let response = match client.get(base_url.clone()).send() {
@@ -321,10 +677,7 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
     let uri = internal_url.clone();
     match client
         .post(uri)
-        .headers(match common_headers(None, settings) {
-            Ok(h) => h,
-            Err(e) => return Err(e),
-        })
+        .headers(common_headers(None, settings)?)
         .header(CONTENT_LENGTH, "0")
         .send()
     {
@@ -339,10 +692,7 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
     };
     let response = match client
         .post(uri)
-        .headers(match common_headers(None, settings) {
-            Ok(h) => h,
-            Err(e) => return Err(e),
-        })
+        .headers(common_headers(None, settings)?)
         .header(CONTENT_LENGTH, "0")
         .send()
     {
@@ -361,6 +711,12 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
         Ok(r) => r,
         Err(e) => return Err(format!("Failed to get resource list path: {}", e)),
     };
+    let sessions_list_path = get_attribute_value(&input, "sessionsProxy", "listAvailableURL")
+        .unwrap_or_else(|_| SESSIONS_LIST_PATH.to_string());
+    let sessions_disconnect_path = get_attribute_value(&input, "sessionsProxy", "disconnectURL")
+        .unwrap_or_else(|_| SESSIONS_DISCONNECT_PATH.to_string());
+    let sessions_logoff_path = get_attribute_value(&input, "sessionsProxy", "logoffURL")
+        .unwrap_or_else(|_| SESSIONS_LOGOFF_PATH.to_string());
 
     // Modify request schema
     // Required for further StoreFront interaction
@@ -375,7 +731,7 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
         ),
         ProtoHeader(
             REFERER,
-            match HeaderValue::from_str(&internal_url.to_string().as_str()) {
+            match HeaderValue::from_str(internal_url.to_string().as_str()) {
                 Ok(h) => h,
                 Err(e) => return Err(format!("Failed to create referer header: {}", e)),
             },
@@ -415,149 +771,307 @@ pub fn get_ica_file(settings: &Settings) -> Result<String, String> {
     };
     let response = match client
         .post(uri)
-        .headers(match common_headers(Some(&custom_headers), settings) {
-            Ok(h) => h,
-            Err(e) => return Err(e),
-        })
+        .headers(common_headers(Some(&custom_headers), settings)?)
         .form(get_list_settings)
         .send()
     {
         Ok(r) => r,
         Err(e) => return Err(format!("Failed to get (fake) resource list: {}", e)),
     };
-    let auth_methods_path = match get_header_attribute(
-        &response.headers(),
+    // Unauthenticated stores hand out the list right away; others ask for credentials
+    let resource_list = match get_header_attribute(
+        response.headers(),
         "CitrixWebReceiver-Authenticate",
         "location",
     ) {
-        Ok(a) => a,
-        Err(e) => return Err(format!("Failed to get auth methods path: {}", e)),
-    };
-
-    // Get auth methods (real) for CitrixAGBasic relative path
-    let uri = match internal_url.join(&auth_methods_path) {
-        Ok(u) => u,
-        Err(e) => return Err(format!("Failed to build URI: {}", e)),
-    };
-    let response = match client
-        .post(uri)
-        .headers(match common_headers(Some(&custom_headers), settings) {
-            Ok(h) => h,
-            Err(e) => return Err(e),
-        })
-        .header(CONTENT_LENGTH, "0")
-        .send()
-    {
-        Ok(r) => r,
-        Err(e) => return Err(format!("Failed to get auth methods: {}", e)),
-    };
-    let input = match response.text() {
-        Ok(i) => i,
-        Err(e) => return Err(format!("Failed to get auth methods: {}", e)),
-    };
-    // TODO: [ISSUE 6] Make this less hacky...
-    let auth_login_path = match get_attribute_value(&input, "method name=\"CitrixAGBasic\"", "url")
-    {
-        Ok(a) => a,
-        Err(e) => return Err(format!("Failed to get auth login path: {}", e)),
+        Ok(auth_methods_path) => {
+            if settings.auth_method == AuthMethod::Anonymous {
+                return Err(
+                    "Store requires authentication, but no credentials are configured".to_string(),
+                );
+            }
+            detail_and_log("Store requires authentication. Logging on to StoreFront...");
+            storefront_logon(
+                &client,
+                &internal_url,
+                &auth_methods_path,
+                &custom_headers,
+                settings,
+            )?;
+            get_resources(
+                &client,
+                &internal_url,
+                &resource_list_path,
+                &custom_headers,
+                settings,
+            )?
+        }
+        Err(e) => {
+            let response_text = match response.text() {
+                Ok(r) => r,
+                Err(e) => return Err(format!("Failed to get response text: {}", e)),
+            };
+            match unauthenticated_resources(&response_text) {
+                Some(r) => {
+                    spit_and_log("Store does not require authentication. Skipping logon.");
+                    r
+                }
+                None => return Err(format!("Failed to get auth methods path: {}", e)),
+            }
+        }
     };
+    detail_and_log(&format!(
+        "Found {} published resources.",
+        resource_list.len()
+    ));
+    Ok(StoreSession {
+        client,
+        base_url,
+        internal_url,
+        csrf_token,
+        custom_headers,
+        resources: resource_list,
+        sessions_list_path,
+        sessions_disconnect_path,
+        sessions_logoff_path,
+        gateway,
+    })
+}
 
-    // Log in to get CtxsAuthId cookie
-    let uri = match internal_url.join(&auth_login_path) {
-        Ok(u) => u,
-        Err(e) => return Err(format!("Failed to build URI: {}", e)),
-    };
-    match client
-        .post(uri)
-        .headers(match common_headers(Some(&custom_headers), settings) {
-            Ok(h) => h,
-            Err(e) => return Err(e),
-        })
-        .header(CONTENT_LENGTH, "0")
-        .send()
-    {
-        Ok(_) => (),
-        Err(e) => return Err(format!("Failed to log in: {}", e)),
-    };
+impl StoreSession {
+    /// Get ICA file for the configured application
+    /// - Finds the application in the session's resource list
+    /// - Reconnects to a disconnected session for the application if one exists
+    /// - Downloads and validates the ICA file
+    /// - Returns a Result with the file name on success, error message on failure
+    pub fn get_ica_file(&self, settings: &Settings) -> Result<String, String> {
+        let application_name = settings.application_name.clone();
 
-    // Get list (should work) to populate ResponseList object
-    let get_list_settings = &[("format", "json"), ("resourceDetails", "Default")];
-    let uri = match internal_url.join(&resource_list_path) {
-        Ok(u) => u,
-        Err(e) => return Err(format!("Failed to build URI: {}", e)),
-    };
-    let response = match client
-        .post(uri)
-        .headers(match common_headers(Some(&custom_headers), settings) {
-            Ok(h) => h,
-            Err(e) => return Err(e),
-        })
-        .form(get_list_settings)
-        .send()
-    {
-        Ok(r) => r,
-        Err(e) => return Err(format!("Failed to get resource list: {}", e)),
-    };
+        // Get target resource
+        let resource = match self
+            .resources
+            .iter()
+            .find(|r| r.name == Some(application_name.clone()))
+        {
+            Some(r) => r,
+            None => return Err("Resource not found".to_string()),
+        };
 
-    // Parse response into ResourceList object
-    let resource_list: Vec<Resource>;
-    let response_text = match response.text() {
-        Ok(r) => r,
-        Err(e) => return Err(format!("Failed to get response text: {}", e)),
-    };
-    match serde_json::from_str::<ResourceList>(response_text.as_str()) {
-        Ok(r) => match r.resources {
-            Some(r) => resource_list = r,
-            None => return Err("No resources found".to_string()),
-        },
-        Err(e) => return Err(format!("Error: {:?}", e)),
-    }
+        // Prefer reconnecting to a disconnected session over starting a new one
+        if let Some(session) = self.disconnected_session(resource, settings) {
+            if let Some(u) = &session.launch_url {
+                spit_and_log(&format!(
+                    "Reconnecting to disconnected session {} on {}...",
+                    session.session_id.as_deref().unwrap_or(""),
+                    session.server_name.as_deref().unwrap_or("unknown server")
+                ));
+                return self.download_ica(u, settings);
+            }
+        }
 
-    // Get ICA URL for target resource
-    let url_result = match resource_list
-        .iter()
-        .find(|r| r.name == Some(application_name.clone()))
-    {
-        Some(r) => match r.launchurl.clone() {
+        // Get ICA URL for target resource
+        let url_result = match resource.launchurl.clone() {
             Some(u) => u,
             None => return Err("No ICA URL found".to_string()),
-        },
-        None => return Err("Resource not found".to_string()),
-    };
+        };
+        self.download_ica(&url_result, settings)
+    }
 
-    // Get ICA file from StoreFront using full URL and validate
-    // TODO: Add this url build to the url build function
-    let file_name = "AutoLaunch.ica";
-    let url = match base_url.join(&format!("{}{}", &internal_url, &url_result)) {
-        Ok(u) => u,
-        Err(e) => return Err(format!("Failed to build URI: {}", e)),
-    };
-    let url = match url.join(&format!("?CsrfToken={}&IsUsingHttps=Yes", csrf_token)) {
-        Ok(u) => u,
-        Err(e) => return Err(format!("Failed to build URI: {}", e)),
-    };
-    let file_response = match client.get(url).send() {
-        Ok(r) => r,
-        Err(e) => return Err(format!("Failed to download file: {:?}", e)),
-    };
-    let mut file = match File::create(file_name) {
-        Ok(f) => f,
-        Err(e) => return Err(format!("Failed to create file: {:?}", e)),
-    };
-    let file_response = match file_response.bytes() {
-        Ok(f) => f,
-        Err(e) => return Err(format!("Failed to get file bytes: {:?}", e)),
-    };
-    let file_response_string = match from_utf8(&file_response) {
-        Ok(f) => f,
-        Err(e) => return Err(format!("Failed to convert file bytes: {:?}", e)),
-    };
-    if file_response_string.contains("[WFClient]") {
-        match copy(&mut file_response.as_ref(), &mut file) {
-            Ok(_) => Ok(file_name.to_string()),
-            Err(e) => Err(format!("Failed to write file: {:?}", e)),
+    /// Get the names of the resources published to the user
+    pub fn resource_names(&self) -> Vec<String> {
+        self.resources
+            .iter()
+            .filter_map(|r| r.name.clone())
+            .collect()
+    }
+
+    /// Get the user's sessions from StoreFront
+    /// - Returns both active and disconnected sessions
+    pub fn sessions(&self, settings: &Settings) -> Result<Vec<UserSession>, String> {
+        let uri = match self.internal_url.join(&self.sessions_list_path) {
+            Ok(u) => u,
+            Err(e) => return Err(format!("Failed to build URI: {}", e)),
+        };
+        let response = match self
+            .client
+            .post(uri)
+            .headers(common_headers(Some(&self.custom_headers), settings)?)
+            .header(CONTENT_LENGTH, "0")
+            .send()
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("Failed to get session list: {}", e)),
+        };
+        let response_text = match response.text() {
+            Ok(r) => r,
+            Err(e) => return Err(format!("Failed to get response text: {}", e)),
+        };
+        match serde_json::from_str::<Vec<UserSession>>(response_text.as_str()) {
+            Ok(s) => Ok(s),
+            Err(e) => Err(format!("Failed to parse session list: {:?}", e)),
+        }
+    }
+
+    /// Disconnect or log off the user's existing sessions
+    /// - Disconnect only affects active sessions; log off affects every session
+    /// - Returns the sessions that were terminated (empty if there was nothing to do)
+    pub fn end_sessions(
+        &self,
+        settings: &Settings,
+        action: SessionCleanup,
+    ) -> Result<Vec<UserSession>, String> {
+        let (path, targets): (&str, Vec<UserSession>) = match action {
+            SessionCleanup::None => return Ok(Vec::new()),
+            SessionCleanup::Disconnect => (
+                &self.sessions_disconnect_path,
+                self.sessions(settings)?
+                    .into_iter()
+                    .filter(|s| s.is_active())
+                    .collect(),
+            ),
+            SessionCleanup::Logoff => (&self.sessions_logoff_path, self.sessions(settings)?),
+        };
+        if targets.is_empty() {
+            return Ok(targets);
+        }
+        let uri = match self.internal_url.join(path) {
+            Ok(u) => u,
+            Err(e) => return Err(format!("Failed to build URI: {}", e)),
+        };
+        let response = match self
+            .client
+            .post(uri)
+            .headers(common_headers(Some(&self.custom_headers), settings)?)
+            .header(CONTENT_LENGTH, "0")
+            .send()
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("Failed to end sessions: {}", e)),
+        };
+        match response.status().is_success() {
+            true => Ok(targets),
+            false => Err(format!(
+                "Failed to end sessions: status {}",
+                response.status()
+            )),
+        }
+    }
+
+    /// Find a disconnected session running the given resource
+    /// - Best effort only; failures to list sessions are logged and treated as no session
+    fn disconnected_session(
+        &self,
+        resource: &Resource,
+        settings: &Settings,
+    ) -> Option<UserSession> {
+        let resource_id = resource.id.as_deref()?;
+        let sessions = match self.sessions(settings) {
+            Ok(s) => s,
+            Err(e) => {
+                spit_and_log(&format!("{} Launching a new session instead.", e));
+                return None;
+            }
+        };
+        sessions
+            .into_iter()
+            .find(|s| s.is_disconnected() && s.runs(resource_id))
+    }
+
+    /// Download an ICA file from StoreFront
+    /// - Accepts the launch URL of a resource or session, relative to StoreFront Web
+    /// - Sends the configured launch arguments with the launch request
+    /// - Returns a Result with the file name on success, error message on failure
+    fn download_ica(&self, launch_url: &str, settings: &Settings) -> Result<String, String> {
+        // Get ICA file from StoreFront using full URL and validate
+        // TODO: Add this url build to the url build function
+        let file_name = ICA_FILE;
+        let url = match self
+            .base_url
+            .join(&format!("{}{}", &self.internal_url, launch_url))
+        {
+            Ok(u) => u,
+            Err(e) => return Err(format!("Failed to build URI: {}", e)),
+        };
+        let mut url = match url.join(&format!("?CsrfToken={}&IsUsingHttps=Yes", self.csrf_token)) {
+            Ok(u) => u,
+            Err(e) => return Err(format!("Failed to build URI: {}", e)),
+        };
+        let client_name = settings.client_name();
+        detail_and_log(&format!(
+            "Downloading ICA file as client {}...",
+            client_name
+        ));
+        url.query_pairs_mut()
+            .append_pair(CLIENT_NAME_PARAMETER, &client_name);
+        if !settings.launch_arguments.is_empty() {
+            url.query_pairs_mut()
+                .append_pair(LAUNCH_ARGUMENTS_PARAMETER, &settings.launch_arguments);
+        }
+        let file_response = match self.client.get(url).send() {
+            Ok(r) => r,
+            Err(e) => return Err(format!("Failed to download file: {:?}", e)),
+        };
+        let mut file = match File::create(file_name) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Failed to create file: {:?}", e)),
+        };
+        let file_response = match file_response.bytes() {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Failed to get file bytes: {:?}", e)),
+        };
+        let file_response_string = match from_utf8(&file_response) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Failed to convert file bytes: {:?}", e)),
+        };
+        if file_response_string.contains("[WFClient]") {
+            let contents = apply_launch_arguments(file_response_string, settings);
+            // Name the session after this device so it can be told apart in Director
+            let contents = set_value(&contents, "WFClient", "ClientName", &client_name);
+            match copy(&mut contents.as_bytes(), &mut file) {
+                Ok(_) => Ok(file_name.to_string()),
+                Err(e) => Err(format!("Failed to write file: {:?}", e)),
+            }
+        } else {
+            Err("Invalid ICA file".to_string())
+        }
+    }
+
+    /// End the web session
+    /// - Logs off StoreFront, then logs out of the gateway if one was used
+    /// - Returns an error describing the first step that failed
+    pub fn logoff(&self, settings: &Settings) -> Result<(), String> {
+        // Log off StoreFront to drop the CtxsAuthId session
+        let uri = match self.internal_url.join(LOGOFF_PATH) {
+            Ok(u) => u,
+            Err(e) => return Err(format!("Failed to build URI: {}", e)),
+        };
+        match self
+            .client
+            .post(uri)
+            .headers(common_headers(Some(&self.custom_headers), settings)?)
+            .header(CONTENT_LENGTH, "0")
+            .send()
+        {
+            Ok(_) => (),
+            Err(e) => return Err(format!("Failed to log off StoreFront: {}", e)),
+        };
+
+        // Log out of the gateway to release the NetScaler session
+        if self.gateway {
+            let uri = match self.base_url.join(GATEWAY_LOGOUT_PATH) {
+                Ok(u) => u,
+                Err(e) => return Err(format!("Failed to build URI: {}", e)),
+            };
+            match self
+                .client
+                .get(uri)
+                .headers(common_headers(None, settings)?)
+                .send()
+            {
+                Ok(_) => (),
+                Err(e) => return Err(format!("Failed to log out of gateway: {}", e)),
+            };
         }
-    } else {
-        Err("Invalid ICA file".to_string())
+        Ok(())
     }
 }
@@ -0,0 +1,256 @@
+use crate::io::Verbosity;
+
+/// Usage text shown for help and for invalid arguments
+pub const USAGE: &str = "Usage: citrix-autolaunch [options] [command]
+
+Commands:
+  run                                   Log in and keep the application running (default)
+  once                                  Launch the application once and exit
+  dry-run                               Log in and check the ICA file without launching
+  setup                                 Create settings if none exist
+  reconfigure                           Replace the existing settings
+  list                                  List the applications published to you
+  test-login                            Log in and out to check the settings
+  show-config                           Show the settings with secrets masked
+  reset                                 Delete the settings file
+  set <field> [value]                   Change one setting (prompts if no value is given)
+  provision [--file <path>] [--<field> <value>...]
+                                        Create settings without prompts from a file,
+                                        CITRIX_AUTOLAUNCH_<FIELD> variables and flags
+  sessions <list|disconnect|logoff>     Manage your existing sessions
+  profiles [list]                       List the profiles (* marks the default)
+  profiles copy <from> <to>             Copy a profile to a new name
+  profiles delete <name>                Delete a profile
+  profiles default <name>               Make a profile the default
+  export <path> [--key-file <path>]     Export the profile encrypted under a passphrase
+  import <path> [--key-file <path>]     Import an exported profile (prompts for the passphrase
+                                        unless a key file is given)
+  version                               Show the version
+  help                                  Show this help
+
+Options:
+  -s, --settings <path>                 Settings file to use (default: the first settings.txt
+                                        found in the user config directory, the system config
+                                        directory, then the executable's directory; new settings
+                                        go to the user config directory)
+  -p, --profile <name>                  Profile to use (default: the default profile)
+  -l, --log <path>                      Log file to use (default: log.txt)
+  -v, --verbose                         Print and log each step in detail
+  -q, --quiet                           Log only; print nothing but command output
+  -h, --help                            Show this help
+  --                                    Take the rest as commands and values, even if they
+                                        start with -";
+
+/// Subcommand to run
+#[derive(PartialEq)]
+pub enum Command {
+    Run,
+    Once,
+    DryRun,
+    Setup,
+    Reconfigure,
+    List,
+    TestLogin,
+    ShowConfig,
+    Reset,
+    Set(String, Option<String>), // Field to change and its new value, if given
+    Provision {
+        file: Option<String>,          // Provisioning file given with --file
+        values: Vec<(String, String)>, // Fields given as --<field> <value> flags
+    },
+    Sessions(SessionAction),
+    Profiles(ProfileAction),
+    Export {
+        path: String,             // Export file to write
+        key_file: Option<String>, // Key file given with --key-file
+    },
+    Import {
+        path: String,             // Export file to read
+        key_file: Option<String>, // Key file given with --key-file
+    },
+    Version,
+    Help,
+}
+
+/// Session management action
+#[derive(PartialEq)]
+pub enum SessionAction {
+    List,
+    Disconnect,
+    Logoff,
+}
+
+/// Profile management action
+#[derive(PartialEq)]
+pub enum ProfileAction {
+    List,
+    Copy(String, String), // From and to
+    Delete(String),
+    Default(String),
+}
+
+/// Parsed command line
+pub struct Cli {
+    pub command: Command,
+    pub settings_path: Option<String>, // Settings file given with --settings
+    pub log_path: Option<String>,      // Log file given with --log
+    pub profile: Option<String>,       // Profile given with --profile
+    pub verbosity: Verbosity,
+}
+
+/// Parse command line arguments (without the program name)
+/// - Options may come before or after the command
+/// - Options taking a value accept `--option value` or `--option=value`
+/// - Everything after `--`, and the value given to set, is taken as is, even if it starts with '-'
+/// - Returns an error String describing the first invalid argument
+pub fn parse(args: &[String]) -> Result<Cli, String> {
+    let mut cli = Cli {
+        command: Command::Run,
+        settings_path: None,
+        log_path: None,
+        profile: None,
+        verbosity: Verbosity::Normal,
+    };
+    let mut words: Vec<&str> = Vec::new();
+    let mut file = None;
+    let mut key_file = None;
+    let mut values = Vec::new();
+    let mut args = args.iter();
+    let mut options_ended = false;
+    while let Some(arg) = args.next() {
+        // A value may start with '-', i.e. set launch_arguments "--screen 3"
+        if options_ended || (words.first() == Some(&"set") && words.len() == 2) {
+            words.push(arg);
+            continue;
+        }
+        if arg == "--" {
+            options_ended = true;
+            continue;
+        }
+        let (option, inline_value) = match arg.split_once('=') {
+            Some((o, v)) if arg.starts_with("--") => (o, Some(v.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        match option {
+            "-s" | "--settings" | "-l" | "--log" | "-p" | "--profile" => {
+                let value = match inline_value.or_else(|| args.next().cloned()) {
+                    Some(v) if !v.is_empty() => v,
+                    _ => return Err(format!("Missing value for {}", option)),
+                };
+                match option {
+                    "-s" | "--settings" => cli.settings_path = Some(value),
+                    "-p" | "--profile" => cli.profile = Some(value),
+                    _ => cli.log_path = Some(value),
+                }
+            }
+            "-v" | "--verbose" => cli.verbosity = Verbosity::Verbose,
+            "-q" | "--quiet" => cli.verbosity = Verbosity::Quiet,
+            "-h" | "--help" => cli.command = Command::Help,
+            "-f" | "--file" => match inline_value.or_else(|| args.next().cloned()) {
+                Some(v) if !v.is_empty() => file = Some(v),
+                _ => return Err(format!("Missing path for {}", option)),
+            },
+            "-k" | "--key-file" => match inline_value.or_else(|| args.next().cloned()) {
+                Some(v) if !v.is_empty() => key_file = Some(v),
+                _ => return Err(format!("Missing path for {}", option)),
+            },
+            // Any other long option names a setting for provision, i.e. --base-uri
+            o if o.starts_with("--") && words.first() == Some(&"provision") => {
+                let value = match inline_value.or_else(|| args.next().cloned()) {
+                    Some(v) => v,
+                    None => return Err(format!("Missing value for {}", o)),
+                };
+                values.push((o[2..].replace('-', "_"), value));
+            }
+            o if o.starts_with('-') => return Err(format!("Unknown option: {}", o)),
+            w => words.push(w),
+        }
+    }
+    if cli.command == Command::Help {
+        return Ok(cli);
+    }
+    if file.is_some() && words.first() != Some(&"provision") {
+        return Err("--file only applies to provision".to_string());
+    }
+    if key_file.is_some() && !matches!(words.first(), Some(&"export") | Some(&"import")) {
+        return Err("--key-file only applies to export and import".to_string());
+    }
+    cli.command = match words.as_slice() {
+        [] | ["run"] => Command::Run,
+        ["once"] => Command::Once,
+        ["dry-run"] => Command::DryRun,
+        ["setup"] => Command::Setup,
+        ["reconfigure"] => Command::Reconfigure,
+        ["list"] => Command::List,
+        ["test-login"] => Command::TestLogin,
+        ["show-config"] => Command::ShowConfig,
+        ["reset"] => Command::Reset,
+        ["set", field] => Command::Set(field.to_string(), None),
+        ["set", field, value] => Command::Set(field.to_string(), Some(value.to_string())),
+        ["provision"] => Command::Provision { file, values },
+        ["sessions"] | ["sessions", "list"] => Command::Sessions(SessionAction::List),
+        ["sessions", "disconnect"] => Command::Sessions(SessionAction::Disconnect),
+        ["sessions", "logoff"] => Command::Sessions(SessionAction::Logoff),
+        ["profiles"] | ["profiles", "list"] => Command::Profiles(ProfileAction::List),
+        ["profiles", "copy", from, to] => {
+            Command::Profiles(ProfileAction::Copy(from.to_string(), to.to_string()))
+        }
+        ["profiles", "delete", name] => Command::Profiles(ProfileAction::Delete(name.to_string())),
+        ["profiles", "default", name] => {
+            Command::Profiles(ProfileAction::Default(name.to_string()))
+        }
+        ["export", path] => Command::Export {
+            path: path.to_string(),
+            key_file,
+        },
+        ["import", path] => Command::Import {
+            path: path.to_string(),
+            key_file,
+        },
+        ["version"] => Command::Version,
+        ["help"] => Command::Help,
+        _ => return Err(format!("Unknown command: {}", words.join(" "))),
+    };
+    Ok(cli)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn set(field: &str, value: &str) -> Command {
+        Command::Set(field.to_string(), Some(value.to_string()))
+    }
+
+    #[test]
+    fn set_values_may_start_with_a_dash() {
+        let cli = parse(&args(&["set", "launch_arguments", "--screen 3"])).unwrap();
+        assert!(cli.command == set("launch_arguments", "--screen 3"));
+        let cli = parse(&args(&["-q", "set", "passwd", "-secret-", "-v"])).unwrap();
+        assert!(cli.command == set("passwd", "-secret-"));
+        assert!(cli.verbosity == Verbosity::Verbose);
+    }
+
+    #[test]
+    fn words_after_the_terminator_are_not_options() {
+        let cli = parse(&args(&["-q", "--", "set", "passwd", "--"])).unwrap();
+        assert!(cli.command == set("passwd", "--"));
+        assert!(cli.verbosity == Verbosity::Quiet);
+        let cli = parse(&args(&["set", "--", "login", "-bob"])).unwrap();
+        assert!(cli.command == set("login", "-bob"));
+        assert!(parse(&args(&["--", "-q"])).is_err());
+    }
+
+    #[test]
+    fn unknown_options_are_still_rejected() {
+        assert_eq!(
+            parse(&args(&["set", "--screen", "login", "x"])).err(),
+            Some("Unknown option: --screen".to_string())
+        );
+        assert!(parse(&args(&["once", "-x"])).is_err());
+    }
+}
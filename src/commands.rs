@@ -0,0 +1,462 @@
+use crate::{
+    citrix::{logon, StoreSession},
+    cli::{ProfileAction, SessionAction},
+    export::{export_profile, import_profile, passphrase},
+    ica::validate,
+    io::{input, pw_input, spit, spit_and_log},
+    launch::launch_file,
+    provision::{environment_values, file_values, provision},
+    storage::{self, SessionCleanup, Settings},
+};
+use std::fs;
+
+/// Exit codes for one-off commands, one per failure category
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_USAGE: i32 = 2; // Unknown command or arguments
+pub const EXIT_SETTINGS: i32 = 3; // Settings missing, unreadable or invalid
+pub const EXIT_LOGIN: i32 = 4; // Gateway or StoreFront login failed
+pub const EXIT_ICA: i32 = 5; // ICA file could not be downloaded or is invalid
+pub const EXIT_LAUNCH: i32 = 6; // Launcher failed to start
+pub const EXIT_SESSIONS: i32 = 7; // Sessions could not be listed or ended
+
+/// Log off the current StoreFront and gateway session, if any
+/// - Errors are logged only; a failed logoff must not block a new login
+pub fn end_session(session: &mut Option<StoreSession>, settings: &Settings) {
+    if let Some(s) = session.take() {
+        spit_and_log("Logging off...");
+        match s.logoff(settings) {
+            Ok(_) => spit_and_log("Logged off successfully."),
+            Err(e) => spit_and_log(&format!("Error: {}\r\n\r\nFailed to log off.", e)),
+        }
+    }
+}
+
+/// Disconnect or log off the user's existing sessions, reporting each one terminated
+/// - Errors are logged only; launching should still be attempted
+/// - Returns false if the sessions could not be ended
+pub fn end_existing_sessions(
+    session: &StoreSession,
+    settings: &Settings,
+    action: SessionCleanup,
+) -> bool {
+    let verb = match action {
+        SessionCleanup::None => return true,
+        SessionCleanup::Disconnect => "Disconnected",
+        SessionCleanup::Logoff => "Logged off",
+    };
+    match session.end_sessions(settings, action) {
+        Ok(ended) if ended.is_empty() => {
+            spit_and_log("No existing sessions to end.");
+            true
+        }
+        Ok(ended) => {
+            for s in ended {
+                spit_and_log(&format!("{}: {}", verb, s));
+            }
+            true
+        }
+        Err(e) => {
+            spit_and_log(&format!(
+                "Error: {}\r\n\r\nFailed to end existing sessions.",
+                e
+            ));
+            false
+        }
+    }
+}
+
+/// Load settings and log in for a one-off command
+/// - Returns the settings and web session, or the exit code for the step that failed
+fn prepare_command() -> Result<(Settings, StoreSession), i32> {
+    let settings = match storage::get_settings() {
+        Ok(s) if s.is_valid() => s,
+        Ok(_) => {
+            spit_and_log("Error: Settings are invalid.");
+            return Err(EXIT_SETTINGS);
+        }
+        Err(e) => {
+            spit_and_log(&format!("Error: {}\r\n\r\nFailed to get settings.", e));
+            return Err(EXIT_SETTINGS);
+        }
+    };
+    match logon(&settings) {
+        Ok(s) => Ok((settings, s)),
+        Err(e) => {
+            spit_and_log(&format!("Error: {}\r\n\r\nFailed to log in.", e));
+            Err(EXIT_LOGIN)
+        }
+    }
+}
+
+/// List, disconnect or log off the user's sessions
+/// - Returns the process exit code
+pub fn run_sessions(action: SessionAction) -> i32 {
+    let (settings, session) = match prepare_command() {
+        Ok(p) => p,
+        Err(code) => return code,
+    };
+    let code = match action {
+        SessionAction::List => match session.sessions(&settings) {
+            Ok(sessions) if sessions.is_empty() => {
+                spit("No sessions found.");
+                EXIT_OK
+            }
+            Ok(sessions) => {
+                for s in sessions {
+                    spit(s);
+                }
+                EXIT_OK
+            }
+            Err(e) => {
+                spit_and_log(&format!("Error: {}\r\n\r\nFailed to list sessions.", e));
+                EXIT_SESSIONS
+            }
+        },
+        SessionAction::Disconnect | SessionAction::Logoff => {
+            let cleanup = match action {
+                SessionAction::Disconnect => SessionCleanup::Disconnect,
+                _ => SessionCleanup::Logoff,
+            };
+            match end_existing_sessions(&session, &settings, cleanup) {
+                true => EXIT_OK,
+                false => EXIT_SESSIONS,
+            }
+        }
+    };
+    end_session(&mut Some(session), &settings);
+    code
+}
+
+/// Log in, download the ICA file and launch it a single time
+/// - Ends existing sessions first if configured, as the launch loop does
+/// - Dry run stops once the ICA file is validated; nothing is ended or launched
+/// - Logs off the web session before returning
+/// - Returns the process exit code
+pub fn run_once(dry_run: bool) -> i32 {
+    let (settings, session) = match prepare_command() {
+        Ok(p) => p,
+        Err(code) => return code,
+    };
+    if !dry_run {
+        end_existing_sessions(&session, &settings, settings.session_cleanup);
+    }
+    let code = match session.get_ica_file(&settings) {
+        Ok(f) => match (validate_file(&f), dry_run) {
+            (Err(e), _) => {
+                spit_and_log(&format!("Error: {}\r\n\r\nICA file is invalid.", e));
+                EXIT_ICA
+            }
+            (Ok(application), true) => {
+                spit_and_log(&format!(
+                    "Dry run: ICA file {} is valid for {}. Not launching.",
+                    f, application
+                ));
+                EXIT_OK
+            }
+            (Ok(_), false) => match launch_file(&f, &settings.launcher, false) {
+                Ok(_) => {
+                    spit_and_log(&format!("File launched successfully: {}", f));
+                    EXIT_OK
+                }
+                Err(e) => {
+                    spit_and_log(&format!("Error: {}\r\n\r\nFailed to launch file.", e));
+                    EXIT_LAUNCH
+                }
+            },
+        },
+        Err(e) => {
+            spit_and_log(&format!("Error: {}\r\n\r\nFailed to get ICA file.", e));
+            EXIT_ICA
+        }
+    };
+    end_session(&mut Some(session), &settings);
+    code
+}
+
+/// Read a downloaded ICA file and check it can be launched
+/// - Returns the application section name on success
+fn validate_file(file_name: &str) -> Result<String, String> {
+    match fs::read_to_string(file_name) {
+        Ok(c) => validate(&c),
+        Err(e) => Err(format!("Failed to read file: {:?}", e)),
+    }
+}
+
+/// Create settings if none exist yet
+/// - Refuses to replace usable settings; reconfigure does that
+/// - Returns the process exit code
+pub fn run_setup() -> i32 {
+    if storage::load_settings().is_ok() {
+        spit_and_log(&format!(
+            "Settings already exist at {}. Use reconfigure to replace them.",
+            storage::settings_path().display()
+        ));
+        return EXIT_SETTINGS;
+    }
+    run_reconfigure()
+}
+
+/// Create new settings, replacing any existing ones
+/// - Returns the process exit code
+pub fn run_reconfigure() -> i32 {
+    match storage::setup_settings() {
+        Ok(_) => {
+            spit_and_log(&format!(
+                "Settings saved to {}.",
+                storage::settings_path().display()
+            ));
+            EXIT_OK
+        }
+        Err(e) => {
+            spit_and_log(&format!("Error: {}\r\n\r\nFailed to save settings.", e));
+            EXIT_SETTINGS
+        }
+    }
+}
+
+/// List the applications published to the user
+/// - Marks the configured application with an asterisk
+/// - Returns the process exit code
+pub fn run_list() -> i32 {
+    let (settings, session) = match prepare_command() {
+        Ok(p) => p,
+        Err(code) => return code,
+    };
+    let names = session.resource_names();
+    if names.is_empty() {
+        spit("No applications found.");
+    }
+    for name in names {
+        match name == settings.application_name {
+            true => spit(format!("* {}", name)),
+            false => spit(format!("  {}", name)),
+        }
+    }
+    end_session(&mut Some(session), &settings);
+    EXIT_OK
+}
+
+/// Log in and out again to check the settings
+/// - Warns if the configured application is not published to the user
+/// - Returns the process exit code
+pub fn run_test_login() -> i32 {
+    let (settings, session) = match prepare_command() {
+        Ok(p) => p,
+        Err(code) => return code,
+    };
+    spit_and_log("Login succeeded.");
+    if !session
+        .resource_names()
+        .contains(&settings.application_name)
+    {
+        spit_and_log(&format!(
+            "Warning: Application {} is not published to this user.",
+            settings.application_name
+        ));
+    }
+    end_session(&mut Some(session), &settings);
+    EXIT_OK
+}
+
+/// Show the settings file in use with secrets masked
+/// - Returns the process exit code
+pub fn run_show_config() -> i32 {
+    let settings = match storage::load_settings() {
+        Ok(s) => s,
+        Err(e) => {
+            spit_and_log(&format!("Error: {}", e));
+            return EXIT_SETTINGS;
+        }
+    };
+    match settings.masked() {
+        Ok(m) => {
+            spit(format!(
+                "Settings file: {}",
+                storage::settings_path().display()
+            ));
+            spit(m);
+            EXIT_OK
+        }
+        Err(e) => {
+            spit_and_log(&format!("Error: {}", e));
+            EXIT_SETTINGS
+        }
+    }
+}
+
+/// Delete the settings file
+/// - Returns the process exit code
+pub fn run_reset() -> i32 {
+    match storage::reset_settings() {
+        Ok(_) => {
+            spit_and_log(&format!(
+                "Settings removed: {}",
+                storage::settings_path().display()
+            ));
+            EXIT_OK
+        }
+        Err(e) => {
+            spit_and_log(&format!("Error: {}", e));
+            EXIT_SETTINGS
+        }
+    }
+}
+
+/// Change one setting and save the settings file
+/// - Prompts for the value if none is given (without echo for secrets)
+/// - Only the changed field is validated
+/// - Returns the process exit code
+pub fn run_set(field: &str, value: Option<String>) -> i32 {
+    let mut settings = match storage::load_settings() {
+        Ok(s) => s,
+        Err(e) => {
+            spit_and_log(&format!("Error: {}", e));
+            return EXIT_SETTINGS;
+        }
+    };
+    let prompt = format!("New value for {}: ", field);
+    let value = match value {
+        Some(v) => Ok(v),
+        None if storage::is_secret_field(field) => pw_input(&prompt),
+        None => input(&prompt),
+    };
+    let value = match value {
+        Ok(v) => v,
+        Err(e) => {
+            spit_and_log(&format!("Error: {}", e));
+            return EXIT_USAGE;
+        }
+    };
+    if let Err(e) = storage::set_field(&mut settings, field, &value) {
+        spit_and_log(&format!("Error: {}", e));
+        return EXIT_USAGE;
+    }
+    match storage::save_settings(&settings) {
+        Ok(_) => {
+            spit_and_log(&format!("Updated {}.", field));
+            EXIT_OK
+        }
+        Err(e) => {
+            spit_and_log(&format!("Error: {}\r\n\r\nFailed to save settings.", e));
+            EXIT_SETTINGS
+        }
+    }
+}
+
+/// Create settings without prompts for unattended provisioning
+/// - Reads the provisioning file, then CITRIX_AUTOLAUNCH_* variables, then flags (later wins)
+/// - Replaces the active profile; other profiles in the settings file are kept
+/// - Returns the process exit code
+pub fn run_provision(file: Option<String>, flags: Vec<(String, String)>) -> i32 {
+    let mut values = match file {
+        Some(f) => match file_values(&f) {
+            Ok(v) => v,
+            Err(e) => {
+                spit_and_log(&format!("Error: {}", e));
+                return EXIT_SETTINGS;
+            }
+        },
+        None => Vec::new(),
+    };
+    values.extend(environment_values());
+    values.extend(flags);
+    match provision(&values) {
+        Ok(_) => {
+            spit_and_log(&format!(
+                "Settings provisioned to {}.",
+                storage::settings_path().display()
+            ));
+            EXIT_OK
+        }
+        Err(e) => {
+            spit_and_log(&format!(
+                "Error: {}\r\n\r\nFailed to provision settings.",
+                e
+            ));
+            EXIT_SETTINGS
+        }
+    }
+}
+
+/// List, copy, delete or choose the default profile
+/// - Returns the process exit code
+pub fn run_profiles(action: ProfileAction) -> i32 {
+    let (result, done) = match action {
+        ProfileAction::List => {
+            let store = match storage::load_store() {
+                Ok(s) => s,
+                Err(e) => {
+                    spit_and_log(&format!("Error: {}", e));
+                    return EXIT_SETTINGS;
+                }
+            };
+            for name in store.profiles.keys() {
+                match *name == store.default_profile {
+                    true => spit(format!("* {}", name)),
+                    false => spit(format!("  {}", name)),
+                }
+            }
+            return EXIT_OK;
+        }
+        ProfileAction::Copy(from, to) => (
+            storage::copy_profile(&from, &to),
+            format!("Copied profile {} to {}.", from, to),
+        ),
+        ProfileAction::Delete(name) => (
+            storage::delete_profile(&name),
+            format!("Deleted profile {}.", name),
+        ),
+        ProfileAction::Default(name) => (
+            storage::set_default_profile(&name),
+            format!("Profile {} is now the default.", name),
+        ),
+    };
+    match result {
+        Ok(_) => {
+            spit_and_log(&done);
+            EXIT_OK
+        }
+        Err(e) => {
+            spit_and_log(&format!("Error: {}", e));
+            EXIT_SETTINGS
+        }
+    }
+}
+
+/// Export the active profile for deployment to other machines
+/// - Prompts for a passphrase (twice) unless a key file is given
+/// - Returns the process exit code
+pub fn run_export(path: &str, key_file: Option<String>) -> i32 {
+    let result = passphrase(key_file.as_deref(), true).and_then(|key| export_profile(path, &key));
+    match result {
+        Ok(name) => {
+            spit_and_log(&format!("Exported profile {} to {}.", name, path));
+            EXIT_OK
+        }
+        Err(e) => {
+            spit_and_log(&format!("Error: {}", e));
+            EXIT_SETTINGS
+        }
+    }
+}
+
+/// Import an exported profile into the settings file
+/// - Prompts for the passphrase unless a key file is given
+/// - Returns the process exit code
+pub fn run_import(path: &str, key_file: Option<String>) -> i32 {
+    let result = passphrase(key_file.as_deref(), false).and_then(|key| import_profile(path, &key));
+    match result {
+        Ok(name) => {
+            spit_and_log(&format!(
+                "Imported profile {} into {}.",
+                name,
+                storage::settings_path().display()
+            ));
+            EXIT_OK
+        }
+        Err(e) => {
+            spit_and_log(&format!("Error: {}", e));
+            EXIT_SETTINGS
+        }
+    }
+}
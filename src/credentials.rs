@@ -0,0 +1,90 @@
+use crate::{
+    io::{detail_and_log, is_interactive},
+    launch::split_command,
+    storage::{CredentialSource, Settings},
+};
+use std::{
+    env, fs,
+    process::{Command, Stdio},
+};
+
+/// Fill in secrets from their configured credential sources, for one logon
+/// - Sources override any secret in the secrets file
+/// - Returns a copy of Settings holding the secrets; never save it
+/// - Returns an error String naming the field whose source could not be read
+pub fn resolve_credentials(settings: &Settings) -> Result<Settings, String> {
+    let mut resolved = settings.clone();
+    for (field, source) in &settings.credential_sources {
+        let value = match read_source(source) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to read {}: {}", field, e)),
+        };
+        match field.strip_prefix("nfactor.") {
+            Some(id) => {
+                resolved.nfactor_fields.insert(id.to_string(), value);
+            }
+            None => resolved.passwd = value,
+        }
+    }
+    Ok(resolved)
+}
+
+/// Read a secret from its source
+/// - A trailing newline, as most files and commands end with, is removed
+/// - Returns an error String if the source is missing, fails or is empty
+fn read_source(source: &CredentialSource) -> Result<String, String> {
+    let value = match source {
+        CredentialSource::Environment(name) => {
+            detail_and_log(&format!(
+                "Reading secret from environment variable {}",
+                name
+            ));
+            match env::var(name) {
+                Ok(v) => v,
+                Err(e) => return Err(format!("environment variable {}: {}", name, e)),
+            }
+        }
+        CredentialSource::File(path) => {
+            detail_and_log(&format!("Reading secret from file {}", path));
+            match fs::read_to_string(path) {
+                Ok(v) => v,
+                Err(e) => return Err(format!("file {}: {:?}", path, e)),
+            }
+        }
+        CredentialSource::Command(line) => {
+            let args = split_command(line);
+            let (program, args) = match args.split_first() {
+                Some(a) => a,
+                None => return Err("empty command".to_string()),
+            };
+            detail_and_log(&format!("Reading secret from command {}", program));
+            // Share the terminal, if any, so a password manager can ask to be unlocked;
+            // unattended, it gets no input rather than waiting on one that never comes
+            let stdin = match is_interactive() {
+                true => Stdio::inherit(),
+                false => Stdio::null(),
+            };
+            let output = match Command::new(program)
+                .args(args)
+                .stdin(stdin)
+                .stderr(Stdio::inherit())
+                .output()
+            {
+                Ok(o) => o,
+                Err(e) => return Err(format!("command {}: {:?}", program, e)),
+            };
+            if !output.status.success() {
+                return Err(format!("command {} exited: {}", program, output.status));
+            }
+            match String::from_utf8(output.stdout) {
+                Ok(v) => v,
+                Err(_) => return Err(format!("command {} printed invalid text", program)),
+            }
+        }
+    };
+    let value = value.trim_end_matches(['\r', '\n']);
+    match value.is_empty() {
+        true => Err("the source is empty".to_string()),
+        false => Ok(value.to_string()),
+    }
+}
@@ -1,6 +1,7 @@
 use const_random::const_random;
 use crypter::{decrypt, encrypt};
-use hex;
+use pbkdf2::pbkdf2_hmac;
+use sha2::Sha256;
 
 /// Generate a key at compile time which persists for all runs (128-bit key)\
 /// - Re-compiling code will generate a new key
@@ -15,9 +16,9 @@ fn get_key() -> String {
 /// - Uses a key generated at compile time
 /// - Returns an error String if encryption fails
 pub fn encrypt_string(data: &str) -> Result<String, String> {
-    match encrypt(&get_key(), data) {
-        Some(enc) => return Ok(hex::encode(enc)),
-        None => return Err(format!("Encryption failed: unknown error")),
+    match encrypt(get_key(), data) {
+        Some(enc) => Ok(hex::encode(enc)),
+        None => Err("Encryption failed: unknown error".to_string()),
     }
 }
 
@@ -30,15 +31,63 @@ pub fn decrypt_string(data: String) -> Result<String, String> {
     // Try to decode the input string
     if let Ok(i) = hex::decode(data) {
         // Try to decrypt the decoded string
-        match decrypt(&get_key(), i) {
+        match decrypt(get_key(), i) {
             // Try to convert the decrypted bytes to a String
             Some(dec) => match String::from_utf8(dec) {
                 Ok(o) => Ok(o),
                 Err(e) => Err(format!("Decryption conversion failed: {:?}", e)),
             },
-            None => Err(format!("Decryption failed: unknown error")),
+            None => Err("Decryption failed: unknown error".to_string()),
         }
     } else {
-        Err(format!("Decryption hex conversion failed"))
+        Err("Decryption hex conversion failed".to_string())
+    }
+}
+
+/// PBKDF2-HMAC-SHA256 rounds used to turn a deployment passphrase into a key
+const PASSPHRASE_ROUNDS: u32 = 600_000;
+/// Length of the random salt stored with data encrypted under a passphrase
+const SALT_LENGTH: usize = 16;
+
+/// Derive a 256-bit key from a passphrase and salt
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, PASSPHRASE_ROUNDS, &mut key);
+    key
+}
+
+/// Encrypt a string under a passphrase instead of the compile-time key
+/// - Accepts a string to encrypt and the passphrase (or key file contents)
+/// - Returns the random salt and encrypted string together as a hex String
+/// - Any build can decrypt it given the same passphrase
+/// - Returns an error String if encryption fails
+pub fn encrypt_with_passphrase(data: &str, passphrase: &str) -> Result<String, String> {
+    let mut salt = [0u8; SALT_LENGTH];
+    if getrandom::getrandom(&mut salt).is_err() {
+        return Err("Encryption failed: no random source for the salt".to_string());
+    }
+    match encrypt(derive_key(passphrase, &salt), data) {
+        Some(enc) => Ok(hex::encode([salt.as_slice(), &enc].concat())),
+        None => Err("Encryption failed: unknown error".to_string()),
+    }
+}
+
+/// Decrypt a string encrypted with encrypt_with_passphrase
+/// - Accepts the hex String and the passphrase (or key file contents)
+/// - Returns the decrypted string as a String
+/// - Returns an error String if the passphrase is wrong or the data is damaged
+pub fn decrypt_with_passphrase(data: &str, passphrase: &str) -> Result<String, String> {
+    let bytes = match hex::decode(data.trim()) {
+        // Salt, then at least the 12-byte nonce the ciphertext ends with
+        Ok(b) if b.len() > SALT_LENGTH + 12 => b,
+        _ => return Err("Decryption hex conversion failed".to_string()),
+    };
+    let (salt, enc) = bytes.split_at(SALT_LENGTH);
+    match decrypt(derive_key(passphrase, salt), enc) {
+        Some(dec) => match String::from_utf8(dec) {
+            Ok(o) => Ok(o),
+            Err(e) => Err(format!("Decryption conversion failed: {:?}", e)),
+        },
+        None => Err("Decryption failed: wrong passphrase or damaged data".to_string()),
     }
 }
@@ -0,0 +1,146 @@
+use crate::{
+    crypto::{decrypt_with_passphrase, encrypt_with_passphrase},
+    io::{is_interactive, pw_input},
+    migrate::{migrate, SCHEMA_VERSION},
+    storage::{
+        load_store, save_store, selected_profile, settings_path, write_atomic, SettingsStore,
+    },
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, from_value, to_string, to_string_pretty, Value};
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// Marks a file as an exported profile
+const EXPORT_FORMAT: &str = "citrix-autolaunch-export";
+/// Layout version of export files (and how their data is encrypted)
+const EXPORT_VERSION: u32 = 1;
+
+/// Exported profile, as written to an export file
+/// - The data is a one-profile SettingsStore, secrets included, encrypted under a passphrase
+#[derive(Deserialize, Serialize)]
+struct Package {
+    format: String, // Always EXPORT_FORMAT
+    version: u32,   // Always EXPORT_VERSION when written
+    data: String,   // Salt and encrypted store as hex (see encrypt_with_passphrase)
+}
+
+/// Get the deployment passphrase for export or import
+/// - Accepts the key file given with --key-file, and whether to confirm a typed passphrase
+/// - A key file's contents are used as the passphrase, without a trailing newline
+/// - Otherwise prompts, which needs a terminal
+/// - Returns an error String if no usable passphrase was given
+pub fn passphrase(key_file: Option<&str>, confirm: bool) -> Result<String, String> {
+    if let Some(path) = key_file {
+        let key = match fs::read_to_string(path) {
+            Ok(k) => k.trim_end_matches(['\r', '\n']).to_string(),
+            Err(e) => return Err(format!("Failed to read key file: {:?}", e)),
+        };
+        return match key.is_empty() {
+            true => Err(format!("The key file {} is empty", path)),
+            false => Ok(key),
+        };
+    }
+    if !is_interactive() {
+        return Err("No terminal to prompt for a passphrase. Give one with --key-file".to_string());
+    }
+    let key = pw_input("Deployment passphrase: ")?;
+    if key.is_empty() {
+        return Err("No passphrase was given".to_string());
+    }
+    if confirm && pw_input("Confirm passphrase: ")? != key {
+        return Err("The passphrases don't match".to_string());
+    }
+    Ok(key)
+}
+
+/// Export the active profile to a file any build can import
+/// - Encrypts the profile, secrets included, under the passphrase instead of the build's key
+/// - Writes the file atomically and readable by its owner only, as settings files are
+/// - Returns the name of the exported profile
+pub fn export_profile(path: &str, passphrase: &str) -> Result<String, String> {
+    let mut store = load_store()?;
+    let name = store.active_profile();
+    let settings = match store.profiles.remove(&name) {
+        Some(s) => s,
+        None => return Err(format!("No profile named {}", name)),
+    };
+    let export = SettingsStore {
+        schema_version: SCHEMA_VERSION,
+        default_profile: name.clone(),
+        profiles: BTreeMap::from([(name.clone(), settings)]),
+    };
+    let data = match to_string(&export) {
+        Ok(d) => d,
+        Err(e) => return Err(format!("Failed to serialize settings: {:?}", e)),
+    };
+    let package = Package {
+        format: EXPORT_FORMAT.to_string(),
+        version: EXPORT_VERSION,
+        data: encrypt_with_passphrase(&data, passphrase)?,
+    };
+    let contents = match to_string_pretty(&package) {
+        Ok(c) => c,
+        Err(e) => return Err(format!("Failed to serialize export: {:?}", e)),
+    };
+    match write_atomic(Path::new(path), contents.as_bytes()) {
+        Ok(_) => Ok(name),
+        Err(e) => Err(format!("Failed to write export file: {}", e)),
+    }
+}
+
+/// Import a profile from an export file, re-encrypting its secrets with this build's key
+/// - Saves it under the name given with --profile, otherwise under its exported name
+/// - Replaces a profile of the same name; other profiles are kept
+/// - Older exports are upgraded as settings files are (see migrate)
+/// - Returns the name the profile was saved as
+pub fn import_profile(path: &str, passphrase: &str) -> Result<String, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => return Err(format!("Failed to read export file: {:?}", e)),
+    };
+    let package: Package = match from_str(&contents) {
+        Ok(p) => p,
+        Err(_) => return Err(format!("{} is not an export file", path)),
+    };
+    if package.format != EXPORT_FORMAT {
+        return Err(format!("{} is not an export file", path));
+    }
+    if package.version > EXPORT_VERSION {
+        return Err(format!(
+            "{} was exported by a newer version of this app. Update this app to import it",
+            path
+        ));
+    }
+    let data = match decrypt_with_passphrase(&package.data, passphrase) {
+        Ok(d) => d,
+        Err(_) => {
+            return Err("The export file could not be decrypted. Check the passphrase".to_string())
+        }
+    };
+    let mut value: Value = match from_str(&data) {
+        Ok(v) => v,
+        Err(_) => return Err("The export file is corrupted".to_string()),
+    };
+    migrate(&mut value)?;
+    let exported: SettingsStore = match from_value(value) {
+        Ok(s) => s,
+        Err(_) => return Err("The export file is corrupted".to_string()),
+    };
+    let (exported_name, settings) = match exported.profiles.into_iter().next() {
+        Some(p) => p,
+        None => return Err("The export file has no profile".to_string()),
+    };
+    let name = selected_profile().unwrap_or(exported_name);
+    // Start a new settings file only if there is none, so an unusable one keeps its profiles
+    let mut store = match load_store() {
+        Ok(s) => s,
+        Err(_) if !settings_path().exists() => SettingsStore::default(),
+        Err(e) => return Err(e),
+    };
+    if store.profiles.is_empty() {
+        store.default_profile = name.clone();
+    }
+    store.profiles.insert(name.clone(), settings);
+    save_store(&store)?;
+    Ok(name)
+}
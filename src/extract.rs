@@ -7,7 +7,7 @@ pub fn get_element_value(body: &str, element: &str) -> Result<String, String> {
     let element_tag = format!("<{}", element.to_lowercase());
     let closing_tag = format!("</{}", element.to_lowercase());
     // find index of element in body
-    let start = match body.to_lowercase().find(&element_tag) {
+    let start = match body.to_ascii_lowercase().find(&element_tag) {
         Some(i) => i + element_tag.len(),
         None => return Err(format!("Element not found: {}", element).to_string()),
     };
@@ -16,7 +16,7 @@ pub fn get_element_value(body: &str, element: &str) -> Result<String, String> {
         None => return Err(format!("Element tag failed to terminate: {}", element).to_string()),
     };
     // find index of closing tag in body
-    let end = match body[start..].to_lowercase().find(&closing_tag) {
+    let end = match body[start..].to_ascii_lowercase().find(&closing_tag) {
         Some(i) => start + i,
         None => {
             return Err(format!("Element value \"{}\" failed to terminate", element).to_string())
@@ -38,7 +38,7 @@ pub fn get_attribute_value(body: &str, element: &str, attribute: &str) -> Result
         None => return Err(format!("Element not found: {}", element).to_string()),
     };
     // find index of attribute in body
-    let start = match body[start..].find(&attribute) {
+    let start = match body[start..].find(attribute) {
         Some(i) => start + i + attribute.len(),
         None => return Err(format!("Attribute not found: {}", attribute).to_string()),
     };
@@ -118,3 +118,101 @@ pub fn get_header_attribute(
         })
         .ok_or_else(|| format!("Attribute not found: {}", attribute))
 }
+
+/// Get the values of every matching element from an HTML or XML body
+/// - Accepts the body as a string and the element name as a string
+/// - Only matches the exact element name (i.e. "Requirement" does not match "Requirements")
+/// - Returns the value of each element in document order (empty if none are found)
+pub fn get_element_values(body: &str, element: &str) -> Vec<String> {
+    // ASCII lowercasing keeps byte offsets, so indexes into lower_body are valid in body
+    let lower_body = body.to_ascii_lowercase();
+    let element_tag = format!("<{}", element.to_lowercase());
+    let closing_tag = format!("</{}>", element.to_lowercase());
+    let mut values = Vec::new();
+    let mut position = 0;
+    while let Some(i) = lower_body[position..].find(&element_tag) {
+        let start = position + i + element_tag.len();
+        // Skip elements which only share a prefix with the requested name
+        // - The name may be followed by any whitespace, i.e. attributes on the next line
+        match lower_body[start..].chars().next() {
+            Some('>') | Some('/') => (),
+            Some(c) if c.is_ascii_whitespace() => (),
+            _ => {
+                position = start;
+                continue;
+            }
+        }
+        let start = match body[start..].find('>') {
+            Some(i) => start + i + 1,
+            None => break,
+        };
+        // Self-closing elements have no value
+        if body[..start].ends_with("/>") {
+            values.push(String::new());
+            position = start;
+            continue;
+        }
+        let end = match lower_body[start..].find(&closing_tag) {
+            Some(i) => start + i,
+            None => break,
+        };
+        values.push(body[start..end].to_string());
+        position = end + closing_tag.len();
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn element_value_is_found_case_insensitively() {
+        let body = "<Result>success</Result><stateContext>abc</stateContext>";
+        assert_eq!(get_element_value(body, "result").unwrap(), "success");
+        assert_eq!(get_element_value(body, "StateContext").unwrap(), "abc");
+        assert!(get_element_value(body, "PostBack").is_err());
+    }
+
+    #[test]
+    fn element_values_are_returned_in_order() {
+        let body = "<List><Item>one</Item><item>two</item><Item a=\"b\">three</Item></List>";
+        assert_eq!(get_element_values(body, "Item"), ["one", "two", "three"]);
+    }
+
+    #[test]
+    fn element_values_skip_elements_sharing_a_prefix() {
+        let body =
+            "<Requirements><Requirement>a</Requirement><Requirement>b</Requirement></Requirements>";
+        assert_eq!(get_element_values(body, "Requirement"), ["a", "b"]);
+    }
+
+    #[test]
+    fn element_values_allow_any_whitespace_after_the_name() {
+        let body = "<Requirement\n  id=\"a\">one</Requirement><Requirement\tid=\"b\">two</Requirement><Requirement\r\nid=\"c\"/>";
+        assert_eq!(get_element_values(body, "Requirement"), ["one", "two", ""]);
+    }
+
+    #[test]
+    fn element_values_include_self_closing_elements_as_blank() {
+        let body = "<Button/><Button>Log On</Button>";
+        assert_eq!(get_element_values(body, "Button"), ["", "Log On"]);
+    }
+
+    #[test]
+    fn element_values_are_empty_when_missing_or_unterminated() {
+        assert!(get_element_values("<Other>x</Other>", "Item").is_empty());
+        assert!(get_element_values("<Item>x", "Item").is_empty());
+    }
+
+    #[test]
+    fn element_values_keep_non_ascii_text() {
+        // Lowercasing İ changes its length in bytes, which must not shift the offsets
+        let body = "<Label>İSTANBUL</Label><Label>Ärger ẞ</Label><Label>ok</Label>";
+        assert_eq!(
+            get_element_values(body, "Label"),
+            ["İSTANBUL", "Ärger ẞ", "ok"]
+        );
+        assert_eq!(get_element_value(body, "Label").unwrap(), "İSTANBUL");
+    }
+}
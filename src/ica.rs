@@ -0,0 +1,112 @@
+/// Get the name of the section holding the launched application's settings
+/// - The first entry under [ApplicationServers] names the application section
+/// - Returns None if the ICA file does not list an application
+pub fn application_section(contents: &str) -> Option<String> {
+    let mut in_servers = false;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_servers = line.eq_ignore_ascii_case("[ApplicationServers]");
+            continue;
+        }
+        if in_servers && !line.is_empty() {
+            return Some(line.split('=').next()?.trim().to_string());
+        }
+    }
+    None
+}
+
+/// Get the value of a key in a section of an ICA file
+/// - Section and key names are matched without regard to case
+/// - Returns None if the section or key is not present
+pub fn get_value(contents: &str, section: &str, key: &str) -> Option<String> {
+    let header = format!("[{}]", section);
+    let mut in_section = false;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_section = line.eq_ignore_ascii_case(&header);
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+        if let Some((k, v)) = line.split_once('=') {
+            if k.trim().eq_ignore_ascii_case(key) {
+                return Some(v.to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Set the value of a key in a section of an ICA file
+/// - Replaces the key if it exists, otherwise adds it at the end of the section
+/// - Adds the section at the end of the file if it does not exist
+/// - Keeps the file's line endings (ICA files normally use CRLF)
+/// - Returns the updated contents
+pub fn set_value(contents: &str, section: &str, key: &str, value: &str) -> String {
+    let newline = match contents.contains("\r\n") {
+        true => "\r\n",
+        false => "\n",
+    };
+    let header = format!("[{}]", section);
+    let entry = format!("{}={}", key, value);
+    let mut lines: Vec<String> = contents.lines().map(|l| l.to_string()).collect();
+    let start = match lines
+        .iter()
+        .position(|l| l.trim().eq_ignore_ascii_case(&header))
+    {
+        Some(i) => i,
+        None => {
+            lines.push(header);
+            lines.push(entry);
+            return lines.join(newline) + newline;
+        }
+    };
+    let end = match lines[start + 1..]
+        .iter()
+        .position(|l| l.trim().starts_with('['))
+    {
+        Some(i) => start + 1 + i,
+        None => lines.len(),
+    };
+    let existing = lines[start + 1..end]
+        .iter()
+        .position(|l| match l.split_once('=') {
+            Some((k, _)) => k.trim().eq_ignore_ascii_case(key),
+            None => false,
+        });
+    match existing {
+        Some(i) => lines[start + 1 + i] = entry,
+        None => {
+            // Insert after the last non-blank line of the section
+            let mut insert_at = end;
+            while insert_at > start + 1 && lines[insert_at - 1].trim().is_empty() {
+                insert_at -= 1;
+            }
+            lines.insert(insert_at, entry);
+        }
+    }
+    lines.join(newline) + newline
+}
+
+/// Check that ICA file contents can be launched
+/// - Requires a [WFClient] section and an application section with an Address
+/// - Returns the application section name on success
+pub fn validate(contents: &str) -> Result<String, String> {
+    if !contents
+        .lines()
+        .any(|l| l.trim().eq_ignore_ascii_case("[WFClient]"))
+    {
+        return Err("Missing [WFClient] section".to_string());
+    }
+    let application = match application_section(contents) {
+        Some(a) => a,
+        None => return Err("No application listed under [ApplicationServers]".to_string()),
+    };
+    match get_value(contents, &application, "Address") {
+        Some(a) if !a.trim().is_empty() => Ok(application),
+        _ => Err(format!("No address for application {}", application)),
+    }
+}
@@ -1,41 +1,98 @@
 use std::{
     fmt::Display,
     fs::OpenOptions,
-    io::{stdin, stdout, BufRead, BufReader, Write},
+    io::{stdin, stdout, BufRead, BufReader, IsTerminal, Write},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        OnceLock,
+    },
 };
 
+/// Log file used unless another path is given on the command line
 const LOG_FILE: &str = "log.txt";
+/// Log file path chosen on the command line, if any
+static LOG_PATH: OnceLock<String> = OnceLock::new();
+/// Current Verbosity, stored as its discriminant
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// How much is written to the console and log
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    Quiet,   // Log only; nothing is printed but prompts and command output
+    Normal,  // Print and log progress and errors
+    Verbose, // Also print and log details, i.e. each login step
+}
+
+/// Set how much is written to the console and log
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::SeqCst);
+}
+
+/// Get how much is written to the console and log
+fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::SeqCst) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+/// Use a different log file than the default
+/// - Only the first call has any effect; call before anything is logged
+pub fn set_log_path(path: &str) {
+    let _ = LOG_PATH.set(path.to_string());
+}
+
+/// Get the path of the log file in use
+fn log_path() -> &'static str {
+    match LOG_PATH.get() {
+        Some(p) => p,
+        None => LOG_FILE,
+    }
+}
+
+/// Number of times a failed password prompt is retried before giving up
+const MAX_PASSWORD_ATTEMPTS: usize = 3;
+
+/// Check if there is a terminal to prompt at
+/// - False when run as a service, from cron or with input redirected
+pub fn is_interactive() -> bool {
+    stdin().is_terminal()
+}
 
 /// Get input from the console
 /// - Accepts a prompt string
 /// - Returns the trimmed input as a String
-pub fn input(prompt: &str) -> String {
+/// - Returns an error when the input ends or can't be read, so callers stop instead of reading blanks
+pub fn input(prompt: &str) -> Result<String, String> {
     print!("{}", prompt);
     // Flush STDOUT; ignore errors
-    if let Ok(_) = stdout().flush() {}
+    let _ = stdout().flush();
     let mut input = String::new();
-    // Get user input; ignore errors
-    if let Ok(_) = stdin().read_line(&mut input) {}
-    input.trim().to_string()
+    match stdin().read_line(&mut input) {
+        Ok(0) => Err("Input ended before a value was entered".to_string()),
+        Ok(_) => Ok(input.trim().to_string()),
+        Err(e) => Err(format!("Failed to read input: {}", e)),
+    }
 }
 
 /// Get password from the console
 /// - Accepts a prompt string
-/// - Returns the trimmed input as a String
-pub fn pw_input(prompt: &str) -> String {
-    let mut success = false;
-    let mut password = String::new();
-    while !success {
-        let result = rpassword::prompt_password(prompt);
-        match result {
-            Ok(pw) => {
-                success = true;
-                password = pw;
-            }
-            Err(_) => (),
+/// - Retries failed prompts up to MAX_PASSWORD_ATTEMPTS times
+/// - Returns the input as typed, untrimmed, since spaces may be part of a password
+/// - Returns an error String if every prompt failed (i.e. there is no terminal)
+pub fn pw_input(prompt: &str) -> Result<String, String> {
+    let mut error = None;
+    for _ in 0..MAX_PASSWORD_ATTEMPTS {
+        match rpassword::prompt_password(prompt) {
+            Ok(pw) => return Ok(pw),
+            Err(e) => error = Some(e),
         }
     }
-    password
+    match error {
+        Some(e) => Err(format!("Failed to read password: {}", e)),
+        None => Err("Failed to read password".to_string()),
+    }
 }
 
 /// Print to console
@@ -47,7 +104,7 @@ pub fn spit(content: impl Display) {
 
 /// Log out to file
 /// - Accepts a string to log
-/// - Logs to the LOG_FILE
+/// - Logs to the LOG_FILE, or the path set with set_log_path
 /// - If the file does not exist, it is created
 /// - If the file exists, the log is appended
 /// - If the file is longer than 500 lines, only the last 500 lines are kept
@@ -57,9 +114,10 @@ pub fn log_to_file(input: &str) {
     // Open file
     let file = OpenOptions::new()
         .create(true)
+        .truncate(false)
         .read(true)
         .write(true)
-        .open(LOG_FILE);
+        .open(log_path());
     let file = match file {
         Ok(file) => file,
         Err(_) => return,
@@ -100,7 +158,7 @@ pub fn log_to_file(input: &str) {
 
         // Create output string
         content = lines.join("\n");
-        if lines.len() > 0 {
+        if !lines.is_empty() {
             content.push('\n');
         }
     } else {
@@ -109,16 +167,27 @@ pub fn log_to_file(input: &str) {
     }
 
     // Write to file
-    if let Ok(_) = std::fs::write(LOG_FILE, content) {}
+    let _ = std::fs::write(log_path(), content);
 }
 
 /// Combination of input and spit
 /// - Accepts a prompt string
-/// - Logs and spits
+/// - Logs and spits (only logs when quiet)
 /// - Does not return anything
 pub fn spit_and_log(input: &str) {
-    log_to_file(&input);
-    spit(&input);
+    log_to_file(input);
+    if verbosity() > Verbosity::Quiet {
+        spit(input);
+    }
+}
+
+/// Combination of input and spit for details only wanted when verbose
+/// - Accepts a prompt string
+/// - Logs and spits when verbose, otherwise does nothing
+pub fn detail_and_log(input: &str) {
+    if verbosity() == Verbosity::Verbose {
+        spit_and_log(input);
+    }
 }
 
 fn condense_repetition(last_line: &str, new_line: &str) -> (bool, String) {
@@ -142,5 +211,5 @@ fn condense_repetition(last_line: &str, new_line: &str) -> (bool, String) {
             .unwrap();
         return (true, format!("{} ({})", new_line, count + 1));
     }
-    return (false, new_line.to_string());
+    (false, new_line.to_string())
 }
@@ -0,0 +1,109 @@
+use crate::{
+    io::spit_and_log,
+    storage::{LaunchCommand, Launcher},
+};
+use std::{
+    env, mem,
+    process::{Command, Stdio},
+    thread,
+};
+
+/// Launch the ICA file with the configured launcher
+/// - Default launcher hands the file to the desktop default handler
+/// - Command launcher runs the command template with {ica} replaced by the file's full path
+/// - Set watch to log the command's exit status and stderr; leave it unset if this process
+///   exits right after launching, so the command keeps writing to our stderr instead
+/// - Returns Result<(), String>
+pub fn launch_file(file_name: &str, launcher: &Launcher, watch: bool) -> Result<(), String> {
+    match launcher {
+        Launcher::Default => match open::that(file_name) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Failed to launch file: {:?}", e)),
+        },
+        Launcher::Command(c) => run_command(file_name, c, watch),
+    }
+}
+
+/// Run an explicit launcher command for the ICA file
+/// - Sets the configured environment variables and working directory
+/// - If watched, the exit status and stderr are logged once the command exits (in the background)
+fn run_command(file_name: &str, launch_command: &LaunchCommand, watch: bool) -> Result<(), String> {
+    // Use the full path, as the command may run in another directory
+    let path = match env::current_dir() {
+        Ok(d) => d.join(file_name),
+        Err(e) => return Err(format!("Failed to get current directory: {:?}", e)),
+    };
+    let args: Vec<String> = split_command(&launch_command.template)
+        .iter()
+        .map(|a| a.replace("{ica}", &path.to_string_lossy()))
+        .collect();
+    let (program, args) = match args.split_first() {
+        Some(a) => a,
+        None => return Err("Launcher command is empty".to_string()),
+    };
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .envs(&launch_command.environment)
+        .stdin(Stdio::null());
+    // A pipe nobody reads once this process exits would fail the command's next write
+    if watch {
+        command.stderr(Stdio::piped());
+    }
+    if !launch_command.working_directory.is_empty() {
+        command.current_dir(&launch_command.working_directory);
+    }
+    let child = match command.spawn() {
+        Ok(c) => c,
+        Err(e) => return Err(format!("Failed to run launcher {}: {:?}", program, e)),
+    };
+    if !watch {
+        return Ok(());
+    }
+    let program = program.clone();
+    thread::spawn(move || match child.wait_with_output() {
+        Ok(o) => {
+            let stderr = String::from_utf8_lossy(&o.stderr);
+            spit_and_log(&format!("Launcher {} exited: {}", program, o.status));
+            if !stderr.trim().is_empty() {
+                spit_and_log(&format!("Launcher stderr: {}", stderr.trim()));
+            }
+        }
+        Err(e) => spit_and_log(&format!("Failed to wait for launcher {}: {:?}", program, e)),
+    });
+    Ok(())
+}
+
+/// Split a command line into arguments
+/// - Arguments are separated by whitespace
+/// - Single or double quotes keep whitespace within an argument, i.e. "C:\Program Files\..."
+pub fn split_command(command: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
@@ -3,16 +3,33 @@
 */
 
 mod citrix;
+mod cli;
+mod commands;
+mod credentials;
 mod crypto;
+mod export;
 mod extract;
+mod ica;
 mod io;
+mod launch;
 mod maximize;
+mod migrate;
+mod negotiate;
+mod nfactor;
+mod process;
+mod provision;
+mod shutdown;
 mod storage;
-use citrix::{get_ica_file, ica_is_running};
-use io::spit_and_log;
+use citrix::{logon, StoreSession, ICA_FILE};
+use cli::{Command, USAGE};
+use commands::{end_existing_sessions, end_session, EXIT_OK, EXIT_USAGE};
+use io::{spit, spit_and_log};
+use launch::launch_file;
 use maximize::maximize_window;
-use std::{thread::sleep, time::Duration};
-use storage::{launch_file, Settings};
+use process::SessionMonitor;
+use shutdown::wait;
+use std::{env, process::exit, time::Duration};
+use storage::Settings;
 
 /// Application state options
 enum State {
@@ -23,29 +40,98 @@ enum State {
     Active,
 }
 
+/// Parse the command line and run the chosen command
+/// - Options for the settings path, profile, log path and verbosity apply to every command
+/// - `run` (the default) keeps the application running; other commands exit with a status code
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let cli = match cli::parse(&args) {
+        Ok(c) => c,
+        Err(e) => {
+            spit(format!("Error: {}\r\n\r\n{}", e, USAGE));
+            exit(EXIT_USAGE);
+        }
+    };
+    if let Some(p) = &cli.settings_path {
+        storage::set_settings_path(p);
+    }
+    if let Some(p) = &cli.profile {
+        storage::set_profile(p);
+    }
+    if let Some(p) = &cli.log_path {
+        io::set_log_path(p);
+    }
+    io::set_verbosity(cli.verbosity);
+    let code = match cli.command {
+        Command::Run => {
+            run();
+            EXIT_OK
+        }
+        Command::Once => commands::run_once(false),
+        Command::DryRun => commands::run_once(true),
+        Command::Setup => commands::run_setup(),
+        Command::Reconfigure => commands::run_reconfigure(),
+        Command::List => commands::run_list(),
+        Command::TestLogin => commands::run_test_login(),
+        Command::ShowConfig => commands::run_show_config(),
+        Command::Reset => commands::run_reset(),
+        Command::Set(field, value) => commands::run_set(&field, value),
+        Command::Provision { file, values } => commands::run_provision(file, values),
+        Command::Sessions(action) => commands::run_sessions(action),
+        Command::Profiles(action) => commands::run_profiles(action),
+        Command::Export { path, key_file } => commands::run_export(&path, key_file),
+        Command::Import { path, key_file } => commands::run_import(&path, key_file),
+        Command::Version => {
+            spit(format!("citrix-autolaunch {}", env!("CARGO_PKG_VERSION")));
+            EXIT_OK
+        }
+        Command::Help => {
+            spit(USAGE);
+            EXIT_OK
+        }
+    };
+    exit(code);
+}
+
 /// Baseline application logical flow
 /// - Check state
 ///   - If encryption key is not set, generate one
 ///     - Success moves on immediately
 ///     - Errors result in a 5 second delay
-///   - If wfica32.exe is running, try to maximize the target window
-///     - 1 second delay before running again
+///   - If the Citrix client (i.e. wfica32.exe) for our session is running, try to maximize the target window
+///     - Waits for the client to exit, waking every second to maximize again if enabled
 ///   - If settings are not loaded or invalid, attempt to load or get them
 ///     - Success moves on immediately
 ///     - Errors result in 5 second delay
+///     - Without a terminal to prompt at, the file is checked again every 30 seconds
 ///   - If ICA file is not downloaded, attempt to get it
 ///     - Success moves on immediately
 ///     - Errors result in 5 second delay
 ///   - If ICA file is downloaded, attempt to launch it
 ///     - Success moves on immediately
 ///     - Errors result in 5 second delay
-fn main() {
+/// - If the process is asked to stop, log off the web session and exit
+///   - With no web session open (i.e. at a settings prompt), exits at once
+fn run() {
     let mut state: State;
     let mut settings = Settings::default();
     let mut file_name = String::new();
+    let mut session: Option<StoreSession> = None;
+    let mut cleanup_pending = true;
+    let mut monitor = SessionMonitor::new(ICA_FILE);
+    if let Err(e) = shutdown::install() {
+        spit_and_log(&e);
+    }
     loop {
+        // Stop cleanly if asked to, so the gateway session isn't left orphaned
+        if shutdown::requested() {
+            end_session(&mut session, &settings);
+            spit_and_log("Stopped.");
+            return;
+        }
+        shutdown::set_cleanup_pending(session.is_some());
         // Check and set state
-        if ica_is_running() {
+        if monitor.is_running(&settings.client_processes()) {
             state = State::Active;
         } else if false {
             // TODO: Implement encryption key check
@@ -70,6 +156,16 @@ fn main() {
                         settings = s;
                         spit_and_log("Settings loaded successfully.");
                     }
+                    // Without a terminal, wait for a settings file to be put in place
+                    Err(e) if !io::is_interactive() => {
+                        let msg = format!(
+                            "Error: {}\r\n\r\nWaiting for settings. Checking again in 30 seconds.",
+                            e
+                        );
+                        settings = Settings::default();
+                        spit_and_log(&msg);
+                        wait(Duration::from_secs(30));
+                    }
                     Err(e) => {
                         let msg = format!(
                             "Error: {}\r\n\r\nFailed to get settings. Retrying in 5 seconds.",
@@ -77,14 +173,30 @@ fn main() {
                         );
                         settings = Settings::default();
                         spit_and_log(&msg);
-                        sleep(Duration::from_secs(5));
+                        wait(Duration::from_secs(5));
                     }
                 };
             }
             State::ReadyToLogIn => {
+                // End the previous web session before logging in again
+                end_session(&mut session, &settings);
                 // Log into Citrix StoreFront and get ICA file
                 spit_and_log("Logging in...");
-                match get_ica_file(&settings) {
+                let result = match logon(&settings) {
+                    Ok(s) => {
+                        // Clear out stale sessions once per start, if configured
+                        if cleanup_pending {
+                            end_existing_sessions(&s, &settings, settings.session_cleanup);
+                            cleanup_pending = false;
+                        }
+                        shutdown::set_cleanup_pending(true);
+                        let result = s.get_ica_file(&settings);
+                        session = Some(s);
+                        result
+                    }
+                    Err(e) => Err(e),
+                };
+                match result {
                     Ok(f) => {
                         file_name = f;
                         spit_and_log("ICA file downloaded successfully.");
@@ -95,22 +207,23 @@ fn main() {
                             e
                         );
                         spit_and_log(&msg);
+                        end_session(&mut session, &settings);
                         settings = Settings::default();
                         file_name = String::new();
-                        sleep(Duration::from_secs(5));
+                        wait(Duration::from_secs(5));
                     }
                 };
             }
             State::ReadyToLaunch => {
-                // Launch ICA file in default application
+                // Launch ICA file with the configured launcher
                 spit_and_log("Launching file...");
                 let target = file_name.clone();
                 file_name = String::new();
-                match launch_file(target.as_str()) {
+                match launch_file(target.as_str(), &settings.launcher, true) {
                     Ok(_) => {
                         let msg = format!("File launched successfully: {}", target);
                         spit_and_log(&msg);
-                        sleep(Duration::from_secs(5));
+                        wait(Duration::from_secs(5));
                     }
                     Err(e) => {
                         let msg = format!(
@@ -119,16 +232,21 @@ fn main() {
                         );
                         spit_and_log(&msg);
                         file_name = String::new();
-                        sleep(Duration::from_secs(5));
+                        wait(Duration::from_secs(5));
                     }
                 };
             }
             State::Active => {
                 // Attempt to maximize target titled window (best effort only)
-                if settings.maximization_active {
-                    maximize_window(&settings.target);
-                }
-                sleep(Duration::from_secs(1));
+                // Then sleep until the client exits, waking each second to maximize again
+                let interval = match settings.maximization_active {
+                    true => {
+                        maximize_window(&settings.target);
+                        Duration::from_secs(1)
+                    }
+                    false => Duration::from_secs(60),
+                };
+                monitor.wait_for_exit(interval);
             }
         }
     }
@@ -4,7 +4,8 @@ use crate::io::spit_and_log;
 use windows_sys::Win32::Foundation::{HWND, LPARAM};
 #[cfg(target_os = "windows")]
 use windows_sys::Win32::UI::WindowsAndMessaging::{
-    EnumWindows, GetWindowTextLengthW, GetWindowTextW, SetForegroundWindow, ShowWindow, SW_MAXIMIZE,
+    EnumWindows, GetWindowTextLengthW, GetWindowTextW, IsWindowVisible, SetForegroundWindow,
+    ShowWindow, SW_MAXIMIZE,
 };
 
 /*********************
@@ -35,6 +36,30 @@ pub fn maximize_window(target: &str) {
     }
 }
 
+#[cfg(target_os = "windows")]
+/// Get the titles of the visible top-level windows, i.e. to pick one to maximize
+/// - Untitled windows are skipped
+pub fn window_titles() -> Vec<String> {
+    unsafe extern "system" fn lpenumfunc(hwnd: HWND, lparam: LPARAM) -> i32 {
+        let titles = &mut *(lparam as *mut Vec<String>);
+        let length = GetWindowTextLengthW(hwnd);
+        if IsWindowVisible(hwnd) == 0 || length == 0 {
+            return 1;
+        }
+        let mut buffer = vec![0u16; length as usize + 1];
+        let copied = GetWindowTextW(hwnd, buffer.as_mut_ptr(), length + 1);
+        titles.push(String::from_utf16_lossy(&buffer[..copied.max(0) as usize]));
+        1
+    }
+
+    let mut titles: Vec<String> = Vec::new();
+    let lparam = &mut titles as *mut Vec<String> as LPARAM;
+    unsafe {
+        EnumWindows(Some(lpenumfunc), lparam);
+    }
+    titles
+}
+
 /***********************
  * Non-Windows Systems *
  **********************/
@@ -46,3 +71,10 @@ pub fn maximize_window(target: &str) {
     spit_and_log(target);
     spit_and_log("Maximize window not supported on this platform.");
 }
+
+#[cfg(not(target_os = "windows"))]
+/// Placeholder function for non-Windows operating systems
+/// - Windows can't be listed here, so returns no titles
+pub fn window_titles() -> Vec<String> {
+    Vec::new()
+}
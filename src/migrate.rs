@@ -0,0 +1,145 @@
+use crate::storage::Settings;
+use serde_json::{to_value, Map, Value};
+
+/// Profile name given to the settings of files written before profiles
+const FIRST_PROFILE: &str = "default";
+
+/// Current settings schema version, stored as schema_version in each settings file
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Start of the error returned for settings written by a newer version of the app
+pub const NEWER_SCHEMA: &str = "Settings file was written by a newer version of this app";
+
+/// Upgrade steps, where MIGRATIONS[n] upgrades schema version n to n + 1
+const MIGRATIONS: [fn(&mut Map<String, Value>); SCHEMA_VERSION as usize] =
+    [unversioned_to_1, single_to_profiles];
+
+/// Upgrade stored settings to the current schema version
+/// - Accepts the decrypted settings file as JSON and upgrades it in place
+/// - Applies each step from the stored version up, then stamps the current version
+/// - Returns the version the settings were stored as
+/// - Returns an error for settings from a newer app, which must not be overwritten
+pub fn migrate(value: &mut Value) -> Result<u32, String> {
+    let settings = match value.as_object_mut() {
+        Some(s) => s,
+        None => return Err("Settings are not a JSON object".to_string()),
+    };
+    let version = match settings.get("schema_version") {
+        None => 0,
+        Some(v) => match v.as_u64() {
+            Some(v) => v as u32,
+            None => return Err("Settings have an invalid schema version".to_string()),
+        },
+    };
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "{} (schema version {}, this version supports up to {}).",
+            NEWER_SCHEMA, version, SCHEMA_VERSION
+        ));
+    }
+    for step in &MIGRATIONS[version as usize..] {
+        step(settings);
+    }
+    settings.insert("schema_version".to_string(), Value::from(SCHEMA_VERSION));
+    Ok(version)
+}
+
+/// Schema version 0 (files written before versioning) to 1
+/// - Fields added since the first release are missing; write them out with their defaults
+fn unversioned_to_1(settings: &mut Map<String, Value>) {
+    if let Ok(Value::Object(defaults)) = to_value(Settings::default()) {
+        for (field, value) in defaults {
+            settings.entry(field).or_insert(value);
+        }
+    }
+}
+
+/// Schema version 1 (one set of settings) to 2 (named profiles)
+/// - The existing settings become the default profile
+fn single_to_profiles(settings: &mut Map<String, Value>) {
+    let mut profile = std::mem::take(settings);
+    profile.remove("schema_version");
+    let mut profiles = Map::new();
+    profiles.insert(FIRST_PROFILE.to_string(), Value::Object(profile));
+    settings.insert("default_profile".to_string(), Value::from(FIRST_PROFILE));
+    settings.insert("profiles".to_string(), Value::Object(profiles));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{AuthMethod, SettingsStore};
+    use serde_json::{from_value, json};
+
+    /// Settings as the first release wrote them, before schema versions
+    fn unversioned() -> Value {
+        json!({
+            "base_uri": "https://store.example.com",
+            "application_name": "Dashboard",
+            "login": "kiosk",
+            "passwd": "secret",
+            "target": "Dashboard",
+            "maximization_active": true
+        })
+    }
+
+    #[test]
+    fn unversioned_settings_are_upgraded_to_profiles() {
+        let mut value = unversioned();
+        assert_eq!(migrate(&mut value), Ok(0));
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+        assert_eq!(value["default_profile"], FIRST_PROFILE);
+        let profile = &value["profiles"][FIRST_PROFILE];
+        assert_eq!(profile["login"], "kiosk");
+        assert_eq!(profile["maximization_active"], true);
+        // Fields added since are filled in with their defaults
+        assert_eq!(profile["auth_method"], "ExplicitForms");
+        assert!(profile.get("schema_version").is_none());
+        let store: SettingsStore = from_value(value).unwrap();
+        let settings = &store.profiles[FIRST_PROFILE];
+        assert_eq!(settings.passwd, "secret");
+        assert!(settings.auth_method == AuthMethod::ExplicitForms);
+    }
+
+    #[test]
+    fn version_1_settings_become_the_default_profile() {
+        let mut value = unversioned();
+        value["schema_version"] = json!(1);
+        value["auth_method"] = json!("Negotiate");
+        assert_eq!(migrate(&mut value), Ok(1));
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+        let profile = &value["profiles"][FIRST_PROFILE];
+        assert_eq!(profile["auth_method"], "Negotiate");
+        assert_eq!(profile["application_name"], "Dashboard");
+        // Version 1 files are complete; nothing is filled in
+        assert!(profile.get("client_name").is_none());
+        assert!(from_value::<SettingsStore>(value).is_ok());
+    }
+
+    #[test]
+    fn current_settings_are_left_unchanged() {
+        let mut value = json!({
+            "schema_version": SCHEMA_VERSION,
+            "default_profile": "lobby",
+            "profiles": {"lobby": {"login": "kiosk"}, "office": {"login": "staff"}}
+        });
+        let original = value.clone();
+        assert_eq!(migrate(&mut value), Ok(SCHEMA_VERSION));
+        assert_eq!(value, original);
+    }
+
+    #[test]
+    fn newer_settings_are_refused() {
+        let mut value = json!({"schema_version": SCHEMA_VERSION + 1, "profiles": {}});
+        let original = value.clone();
+        let error = migrate(&mut value).unwrap_err();
+        assert!(error.starts_with(NEWER_SCHEMA), "{}", error);
+        assert_eq!(value, original);
+    }
+
+    #[test]
+    fn invalid_layouts_are_errors() {
+        assert!(migrate(&mut json!("settings")).is_err());
+        assert!(migrate(&mut json!({"schema_version": "two"})).is_err());
+    }
+}
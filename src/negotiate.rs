@@ -0,0 +1,402 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+#[cfg(not(target_os = "windows"))]
+use libloading::{Library, Symbol};
+#[cfg(target_os = "windows")]
+use std::{ffi::c_void, ptr::null, ptr::null_mut};
+#[cfg(not(target_os = "windows"))]
+use std::{ffi::c_void, ptr::null_mut};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Foundation::{SEC_E_OK, SEC_I_CONTINUE_NEEDED};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Security::Authentication::Identity::{
+    AcquireCredentialsHandleW, DeleteSecurityContext, FreeContextBuffer, FreeCredentialsHandle,
+    InitializeSecurityContextW, SecBuffer, SecBufferDesc, ISC_REQ_ALLOCATE_MEMORY,
+    ISC_REQ_MUTUAL_AUTH, SECBUFFER_TOKEN, SECBUFFER_VERSION, SECPKG_CRED_OUTBOUND,
+    SECURITY_NATIVE_DREP,
+};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Security::Credentials::SecHandle;
+
+/// Build the value of an Authorization header for Negotiate (SPNEGO) authentication
+/// - Accepts the host name of the server (the service principal is HTTP@host)
+/// - Uses the credentials already held by the current user (Kerberos ticket cache or Windows logon)
+/// - Returns the full header value, i.e. "Negotiate YIIH..."
+pub fn authorization_header(host: &str) -> Result<String, String> {
+    let token = initial_token(host)?;
+    Ok(format!("Negotiate {}", STANDARD.encode(token)))
+}
+
+/*******************
+ * Unix Functions *
+ ******************/
+
+#[cfg(not(target_os = "windows"))]
+type OmUint32 = u32;
+
+#[cfg(not(target_os = "windows"))]
+#[repr(C)]
+struct GssBuffer {
+    length: usize,
+    value: *mut c_void,
+}
+
+// Apple's GSS framework packs OIDs to 2 bytes; MIT and Heimdal elsewhere use natural alignment
+#[cfg(not(target_os = "windows"))]
+#[cfg_attr(target_os = "macos", repr(C, packed(2)))]
+#[cfg_attr(not(target_os = "macos"), repr(C))]
+struct GssOid {
+    length: OmUint32,
+    elements: *mut c_void,
+}
+
+#[cfg(not(target_os = "windows"))]
+type GssImportName =
+    unsafe extern "C" fn(*mut OmUint32, *mut GssBuffer, *mut GssOid, *mut *mut c_void) -> OmUint32;
+#[cfg(not(target_os = "windows"))]
+type GssInitSecContext = unsafe extern "C" fn(
+    *mut OmUint32,
+    *mut c_void,
+    *mut *mut c_void,
+    *mut c_void,
+    *mut GssOid,
+    OmUint32,
+    OmUint32,
+    *mut c_void,
+    *mut GssBuffer,
+    *mut *mut GssOid,
+    *mut GssBuffer,
+    *mut OmUint32,
+    *mut OmUint32,
+) -> OmUint32;
+#[cfg(not(target_os = "windows"))]
+type GssDisplayStatus = unsafe extern "C" fn(
+    *mut OmUint32,
+    OmUint32,
+    i32,
+    *mut GssOid,
+    *mut OmUint32,
+    *mut GssBuffer,
+) -> OmUint32;
+#[cfg(not(target_os = "windows"))]
+type GssReleaseBuffer = unsafe extern "C" fn(*mut OmUint32, *mut GssBuffer) -> OmUint32;
+#[cfg(not(target_os = "windows"))]
+type GssReleaseName = unsafe extern "C" fn(*mut OmUint32, *mut *mut c_void) -> OmUint32;
+#[cfg(not(target_os = "windows"))]
+type GssDeleteSecContext =
+    unsafe extern "C" fn(*mut OmUint32, *mut *mut c_void, *mut GssBuffer) -> OmUint32;
+
+/// GSSAPI libraries to try, in order (MIT on Linux, Apple GSS framework, Heimdal)
+#[cfg(not(target_os = "windows"))]
+const GSSAPI_LIBRARIES: [&str; 4] = [
+    "libgssapi_krb5.so.2",
+    "/System/Library/Frameworks/GSS.framework/GSS",
+    "libgssapi_krb5.so",
+    "libgssapi.so.3",
+];
+
+/// GSS_C_NT_HOSTBASED_SERVICE (1.2.840.113554.1.2.1.4)
+#[cfg(not(target_os = "windows"))]
+const HOSTBASED_SERVICE_OID: [u8; 10] =
+    [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x01, 0x04];
+/// SPNEGO mechanism (1.3.6.1.5.5.2)
+#[cfg(not(target_os = "windows"))]
+const SPNEGO_OID: [u8; 6] = [0x2b, 0x06, 0x01, 0x05, 0x05, 0x02];
+#[cfg(not(target_os = "windows"))]
+const GSS_C_MUTUAL_FLAG: OmUint32 = 2;
+#[cfg(not(target_os = "windows"))]
+const GSS_C_GSS_CODE: i32 = 1;
+#[cfg(not(target_os = "windows"))]
+const GSS_C_MECH_CODE: i32 = 2;
+
+#[cfg(not(target_os = "windows"))]
+/// Check whether a GSSAPI major status is an error
+fn gss_error(major: OmUint32) -> bool {
+    major & 0xffff0000 != 0
+}
+
+#[cfg(not(target_os = "windows"))]
+/// Load the first available GSSAPI library
+fn load_gssapi() -> Result<Library, String> {
+    for name in GSSAPI_LIBRARIES {
+        // Loading a system library runs no initialization beyond what the system provides
+        if let Ok(library) = unsafe { Library::new(name) } {
+            return Ok(library);
+        }
+    }
+    Err("No GSSAPI library found (install MIT Kerberos or Heimdal)".to_string())
+}
+
+#[cfg(not(target_os = "windows"))]
+/// Describe a GSSAPI failure using the library's own messages
+fn describe_status(library: &Library, major: OmUint32, minor: OmUint32) -> String {
+    let display: Symbol<GssDisplayStatus> = match unsafe { library.get(b"gss_display_status\0") } {
+        Ok(f) => f,
+        Err(_) => return format!("GSSAPI error {:#x} (minor {})", major, minor),
+    };
+    let release: Symbol<GssReleaseBuffer> = match unsafe { library.get(b"gss_release_buffer\0") } {
+        Ok(f) => f,
+        Err(_) => return format!("GSSAPI error {:#x} (minor {})", major, minor),
+    };
+    let mut messages = Vec::new();
+    for (code, kind) in [(major, GSS_C_GSS_CODE), (minor, GSS_C_MECH_CODE)] {
+        if code == 0 {
+            continue;
+        }
+        let mut context: OmUint32 = 0;
+        loop {
+            let mut status: OmUint32 = 0;
+            let mut buffer = GssBuffer {
+                length: 0,
+                value: null_mut(),
+            };
+            let result = unsafe {
+                display(
+                    &mut status,
+                    code,
+                    kind,
+                    null_mut(),
+                    &mut context,
+                    &mut buffer,
+                )
+            };
+            if gss_error(result) {
+                break;
+            }
+            if !buffer.value.is_null() {
+                let bytes =
+                    unsafe { std::slice::from_raw_parts(buffer.value as *const u8, buffer.length) };
+                messages.push(
+                    String::from_utf8_lossy(bytes)
+                        .trim_end_matches('\0')
+                        .to_string(),
+                );
+                unsafe { release(&mut status, &mut buffer) };
+            }
+            if context == 0 {
+                break;
+            }
+        }
+    }
+    match messages.is_empty() {
+        true => format!("GSSAPI error {:#x} (minor {})", major, minor),
+        false => messages.join(": "),
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+/// Create the first SPNEGO token for HTTP@host using the default credential cache
+fn initial_token(host: &str) -> Result<Vec<u8>, String> {
+    let library = load_gssapi()?;
+    let import_name: Symbol<GssImportName> = match unsafe { library.get(b"gss_import_name\0") } {
+        Ok(f) => f,
+        Err(e) => return Err(format!("GSSAPI library is missing gss_import_name: {}", e)),
+    };
+    let init_sec_context: Symbol<GssInitSecContext> =
+        match unsafe { library.get(b"gss_init_sec_context\0") } {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(format!(
+                    "GSSAPI library is missing gss_init_sec_context: {}",
+                    e
+                ))
+            }
+        };
+    let release_buffer: Symbol<GssReleaseBuffer> =
+        match unsafe { library.get(b"gss_release_buffer\0") } {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(format!(
+                    "GSSAPI library is missing gss_release_buffer: {}",
+                    e
+                ))
+            }
+        };
+    let release_name: Symbol<GssReleaseName> = match unsafe { library.get(b"gss_release_name\0") } {
+        Ok(f) => f,
+        Err(e) => return Err(format!("GSSAPI library is missing gss_release_name: {}", e)),
+    };
+    let delete_sec_context: Symbol<GssDeleteSecContext> =
+        match unsafe { library.get(b"gss_delete_sec_context\0") } {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(format!(
+                    "GSSAPI library is missing gss_delete_sec_context: {}",
+                    e
+                ))
+            }
+        };
+
+    // Import the service principal name
+    let mut service = format!("HTTP@{}", host).into_bytes();
+    let mut service_buffer = GssBuffer {
+        length: service.len(),
+        value: service.as_mut_ptr() as *mut c_void,
+    };
+    let mut hostbased = HOSTBASED_SERVICE_OID;
+    let mut hostbased_oid = GssOid {
+        length: hostbased.len() as OmUint32,
+        elements: hostbased.as_mut_ptr() as *mut c_void,
+    };
+    let mut minor: OmUint32 = 0;
+    let mut target: *mut c_void = null_mut();
+    let major = unsafe {
+        import_name(
+            &mut minor,
+            &mut service_buffer,
+            &mut hostbased_oid,
+            &mut target,
+        )
+    };
+    if gss_error(major) {
+        return Err(format!(
+            "Failed to import service name HTTP@{}: {}",
+            host,
+            describe_status(&library, major, minor)
+        ));
+    }
+
+    // Initialize the security context with the default credentials
+    let mut spnego = SPNEGO_OID;
+    let mut spnego_oid = GssOid {
+        length: spnego.len() as OmUint32,
+        elements: spnego.as_mut_ptr() as *mut c_void,
+    };
+    let mut context: *mut c_void = null_mut();
+    let mut output = GssBuffer {
+        length: 0,
+        value: null_mut(),
+    };
+    let major = unsafe {
+        init_sec_context(
+            &mut minor,
+            null_mut(),
+            &mut context,
+            target,
+            &mut spnego_oid,
+            GSS_C_MUTUAL_FLAG,
+            0,
+            null_mut(),
+            null_mut(),
+            null_mut(),
+            &mut output,
+            null_mut(),
+            null_mut(),
+        )
+    };
+    let result = match gss_error(major) {
+        true => Err(format!(
+            "Failed to create Negotiate token for HTTP@{}: {}",
+            host,
+            describe_status(&library, major, minor)
+        )),
+        false if output.value.is_null() || output.length == 0 => {
+            Err("GSSAPI returned an empty Negotiate token".to_string())
+        }
+        false => Ok(unsafe {
+            std::slice::from_raw_parts(output.value as *const u8, output.length).to_vec()
+        }),
+    };
+
+    // Release everything GSSAPI allocated
+    let mut status: OmUint32 = 0;
+    unsafe {
+        if !output.value.is_null() {
+            release_buffer(&mut status, &mut output);
+        }
+        if !context.is_null() {
+            delete_sec_context(&mut status, &mut context, null_mut());
+        }
+        release_name(&mut status, &mut target);
+    }
+    result
+}
+
+/*********************
+ * Windows Functions *
+ ********************/
+
+#[cfg(target_os = "windows")]
+/// Create the first SPNEGO token for HTTP/host using the current Windows logon
+fn initial_token(host: &str) -> Result<Vec<u8>, String> {
+    let package: Vec<u16> = "Negotiate\0".encode_utf16().collect();
+    let target: Vec<u16> = format!("HTTP/{}\0", host).encode_utf16().collect();
+    let mut credentials = SecHandle {
+        dwLower: 0,
+        dwUpper: 0,
+    };
+    let mut expiry: i64 = 0;
+    let status = unsafe {
+        AcquireCredentialsHandleW(
+            null(),
+            package.as_ptr(),
+            SECPKG_CRED_OUTBOUND,
+            null(),
+            null(),
+            None,
+            null(),
+            &mut credentials,
+            &mut expiry,
+        )
+    };
+    if status != SEC_E_OK {
+        return Err(format!(
+            "Failed to acquire Windows credentials: {:#x}",
+            status
+        ));
+    }
+
+    let mut context = SecHandle {
+        dwLower: 0,
+        dwUpper: 0,
+    };
+    let mut buffer = SecBuffer {
+        cbBuffer: 0,
+        BufferType: SECBUFFER_TOKEN,
+        pvBuffer: null_mut(),
+    };
+    let mut output = SecBufferDesc {
+        ulVersion: SECBUFFER_VERSION,
+        cBuffers: 1,
+        pBuffers: &mut buffer,
+    };
+    let mut attributes: u32 = 0;
+    let status = unsafe {
+        InitializeSecurityContextW(
+            &credentials,
+            null(),
+            target.as_ptr(),
+            ISC_REQ_ALLOCATE_MEMORY | ISC_REQ_MUTUAL_AUTH,
+            0,
+            SECURITY_NATIVE_DREP,
+            null(),
+            0,
+            &mut context,
+            &mut output,
+            &mut attributes,
+            &mut expiry,
+        )
+    };
+    let result = match status == SEC_E_OK || status == SEC_I_CONTINUE_NEEDED {
+        true if !buffer.pvBuffer.is_null() && buffer.cbBuffer > 0 => Ok(unsafe {
+            std::slice::from_raw_parts(buffer.pvBuffer as *const u8, buffer.cbBuffer as usize)
+                .to_vec()
+        }),
+        true => Err("SSPI returned an empty Negotiate token".to_string()),
+        false => Err(format!(
+            "Failed to create Negotiate token for HTTP/{}: {:#x}",
+            host, status
+        )),
+    };
+
+    // Release everything SSPI allocated
+    unsafe {
+        if !buffer.pvBuffer.is_null() {
+            FreeContextBuffer(buffer.pvBuffer as *mut c_void);
+        }
+        if status == SEC_E_OK || status == SEC_I_CONTINUE_NEEDED {
+            DeleteSecurityContext(&context);
+        }
+        FreeCredentialsHandle(&credentials);
+    }
+    result
+}
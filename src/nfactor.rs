@@ -0,0 +1,263 @@
+use crate::{
+    extract::{get_element_value, get_element_values},
+    storage::Settings,
+};
+
+/// Default nFactor schema path, used when the gateway does not advertise ExplicitForms
+/// - Absolute, since it sits at the gateway's root whatever path the base URL has
+pub const LOGIN_SCHEMA_PATH: &str = "/nf/auth/getAuthenticationRequirements.do";
+
+/// Maximum number of factors to answer before giving up on a login
+pub const MAX_FACTORS: usize = 10;
+
+/// Single credential requirement from an nFactor LoginSchema
+struct Requirement {
+    id: String,              // Form field name expected by the gateway
+    credential_type: String, // username, password, savecredentials, none, etc.
+    label: String,           // Text shown to the user for this field
+    button: Option<String>,  // Button text, if the requirement is a submit button
+}
+
+/// LoginSchema (AuthenticateResponse) served by an nFactor gateway
+pub struct LoginSchema {
+    pub result: String, // Empty or "more-info" while factors remain, then "success" or "fail"
+    pub state_context: String, // Opaque state which must be posted back with each factor
+    pub postback: String, // Path to post the next factor to
+    pub redirect_url: String, // Path to follow once authentication succeeds
+    requirements: Vec<Requirement>,
+}
+
+impl LoginSchema {
+    /// Parse a LoginSchema from an AuthenticateResponse body
+    /// - Missing elements are treated as blank, as gateways omit what they don't need
+    pub fn parse(body: &str) -> Result<LoginSchema, String> {
+        if !body.contains("AuthenticateResponse") {
+            return Err("Response is not an nFactor login schema".to_string());
+        }
+        let requirements = get_element_values(body, "Requirement")
+            .iter()
+            .filter_map(|r| {
+                let credential = get_element_value(r, "Credential").unwrap_or_default();
+                let label = get_element_value(r, "Label").unwrap_or_default();
+                Some(Requirement {
+                    id: get_element_value(&credential, "ID")
+                        .ok()?
+                        .trim()
+                        .to_string(),
+                    credential_type: get_element_value(&credential, "Type")
+                        .unwrap_or_default()
+                        .trim()
+                        .to_lowercase(),
+                    label: get_element_value(&label, "Text")
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string(),
+                    button: get_element_value(r, "Button")
+                        .ok()
+                        .map(|b| b.trim().to_string()),
+                })
+            })
+            .collect();
+        Ok(LoginSchema {
+            result: get_element_value(body, "Result")
+                .unwrap_or_default()
+                .trim()
+                .to_lowercase(),
+            state_context: get_element_value(body, "StateContext").unwrap_or_default(),
+            postback: get_element_value(body, "PostBack")
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            redirect_url: get_element_value(body, "RedirectURL")
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            requirements,
+        })
+    }
+
+    /// Check whether the gateway accepted every factor
+    pub fn is_success(&self) -> bool {
+        self.result == "success" || (self.requirements.is_empty() && !self.redirect_url.is_empty())
+    }
+
+    /// Check whether the gateway rejected the last factor
+    pub fn is_failure(&self) -> bool {
+        self.result == "fail"
+    }
+
+    /// Build the form for the current factor from Settings
+    /// - Fields named in `nfactor_fields` always win, so any dynamic field can be answered
+    /// - Otherwise, username and password fields use the stored login and passwd
+    /// - Returns an error naming the field if no value is available
+    pub fn credentials(&self, settings: &Settings) -> Result<Vec<(String, String)>, String> {
+        let mut form = Vec::new();
+        for requirement in &self.requirements {
+            let value = match settings.nfactor_fields.get(&requirement.id) {
+                Some(v) => v.clone(),
+                None => match requirement.credential_type.as_str() {
+                    "username" => settings.login.clone(),
+                    "password" => settings.passwd.clone(),
+                    "savecredentials" => "false".to_string(),
+                    _ => match &requirement.button {
+                        Some(b) => b.clone(),
+                        None => {
+                            return Err(format!(
+                                "No value configured for nFactor field \"{}\" ({})",
+                                requirement.id, requirement.label
+                            ))
+                        }
+                    },
+                },
+            };
+            form.push((requirement.id.clone(), value));
+        }
+        form.push(("StateContext".to_string(), self.state_context.clone()));
+        Ok(form)
+    }
+
+    /// Get a readable reason for a failed factor from the schema labels
+    pub fn failure_reason(&self) -> String {
+        match self.requirements.iter().find(|r| !r.label.is_empty()) {
+            Some(r) => r.label.clone(),
+            None => "Gateway rejected the credentials".to_string(),
+        }
+    }
+}
+
+/// Check whether an auth requirements response is an nFactor LoginSchema
+/// - Classic gateways post back to /cgi/login or /p/u/doAuthentication.do
+/// - nFactor gateways post back to /nf/auth/doAuthentication.do
+pub fn is_login_schema(body: &str) -> bool {
+    match get_element_value(body, "PostBack") {
+        Ok(p) => p.trim().starts_with("/nf/"),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// First factor as served by a NetScaler nFactor gateway, trimmed to what is parsed
+    const SCHEMA: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<AuthenticateResponse xmlns="http://citrix.com/authentication/response/1">
+<Status>success</Status>
+<Result>more-info</Result>
+<StateContext>state-1</StateContext>
+<AuthenticationRequirements>
+<PostBack>/nf/auth/doAuthentication.do</PostBack>
+<Requirements>
+<Requirement><Credential><ID>login</ID><Type>username</Type></Credential><Label><Text>User name</Text><Type>plain</Type></Label><Input><Text><Secret>false</Secret></Text></Input></Requirement>
+<Requirement><Credential><ID>passwd</ID><Type>password</Type></Credential><Label><Text>Password:</Text><Type>plain</Type></Label><Input><Text><Secret>true</Secret></Text></Input></Requirement>
+<Requirement><Credential><ID>otp</ID><Type>none</Type></Credential><Label><Text>Token code</Text><Type>plain</Type></Label><Input><Text><Secret>true</Secret></Text></Input></Requirement>
+<Requirement><Credential><ID>saveCredentials</ID><Type>savecredentials</Type></Credential><Label><Type>none</Type></Label><Input><CheckBox><InitialValue>false</InitialValue></CheckBox></Input></Requirement>
+<Requirement><Credential><ID>loginBtn</ID><Type>none</Type></Credential><Label><Type>none</Type></Label><Input><Button>Log On</Button></Input></Requirement>
+</Requirements>
+</AuthenticationRequirements>
+</AuthenticateResponse>"#;
+
+    fn settings() -> Settings {
+        Settings {
+            login: "jdoe".to_string(),
+            passwd: "secret".to_string(),
+            nfactor_fields: [("otp".to_string(), "123456".to_string())].into(),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn parse_reads_state_and_requirements() {
+        let schema = LoginSchema::parse(SCHEMA).unwrap();
+        assert_eq!(schema.result, "more-info");
+        assert_eq!(schema.state_context, "state-1");
+        assert_eq!(schema.postback, "/nf/auth/doAuthentication.do");
+        assert_eq!(schema.redirect_url, "");
+        let ids: Vec<&str> = schema.requirements.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            ["login", "passwd", "otp", "saveCredentials", "loginBtn"]
+        );
+        assert_eq!(schema.requirements[0].credential_type, "username");
+        assert_eq!(schema.requirements[2].label, "Token code");
+        assert_eq!(schema.requirements[4].button.as_deref(), Some("Log On"));
+        assert!(schema.requirements[0].button.is_none());
+        assert!(!schema.is_success());
+        assert!(!schema.is_failure());
+    }
+
+    #[test]
+    fn parse_rejects_other_responses() {
+        assert!(LoginSchema::parse("<html><body>Log On</body></html>").is_err());
+    }
+
+    #[test]
+    fn parse_recognizes_success_and_failure() {
+        let success = "<AuthenticateResponse><Result>success</Result><StateContext>s</StateContext></AuthenticateResponse>";
+        assert!(LoginSchema::parse(success).unwrap().is_success());
+        let redirect = "<AuthenticateResponse><RedirectURL>/p/u/setClient.do</RedirectURL></AuthenticateResponse>";
+        let schema = LoginSchema::parse(redirect).unwrap();
+        assert!(schema.is_success());
+        assert_eq!(schema.redirect_url, "/p/u/setClient.do");
+        let fail = "<AuthenticateResponse><Result>fail</Result></AuthenticateResponse>";
+        assert!(LoginSchema::parse(fail).unwrap().is_failure());
+    }
+
+    #[test]
+    fn credentials_answer_every_requirement() {
+        let schema = LoginSchema::parse(SCHEMA).unwrap();
+        let form = schema.credentials(&settings()).unwrap();
+        let expected = [
+            ("login", "jdoe"),
+            ("passwd", "secret"),
+            ("otp", "123456"),
+            ("saveCredentials", "false"),
+            ("loginBtn", "Log On"),
+            ("StateContext", "state-1"),
+        ];
+        let form: Vec<(&str, &str)> = form.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(form, expected);
+    }
+
+    #[test]
+    fn credentials_prefer_configured_nfactor_fields() {
+        let schema = LoginSchema::parse(SCHEMA).unwrap();
+        let mut settings = settings();
+        settings
+            .nfactor_fields
+            .insert("passwd".to_string(), "override".to_string());
+        let form = schema.credentials(&settings).unwrap();
+        assert!(form.contains(&("passwd".to_string(), "override".to_string())));
+    }
+
+    #[test]
+    fn credentials_name_a_missing_field() {
+        let schema = LoginSchema::parse(SCHEMA).unwrap();
+        let mut settings = settings();
+        settings.nfactor_fields.clear();
+        let error = schema.credentials(&settings).unwrap_err();
+        assert!(error.contains("\"otp\""));
+        assert!(error.contains("Token code"));
+    }
+
+    #[test]
+    fn failure_reason_uses_the_first_label() {
+        let fail = "<AuthenticateResponse><Result>fail</Result><Requirements><Requirement><Credential><ID>x</ID></Credential><Label><Text>Incorrect credentials. Try again.</Text></Label></Requirement></Requirements></AuthenticateResponse>";
+        let schema = LoginSchema::parse(fail).unwrap();
+        assert_eq!(schema.failure_reason(), "Incorrect credentials. Try again.");
+        let bare = LoginSchema::parse(
+            "<AuthenticateResponse><Result>fail</Result></AuthenticateResponse>",
+        )
+        .unwrap();
+        assert_eq!(bare.failure_reason(), "Gateway rejected the credentials");
+    }
+
+    #[test]
+    fn login_schema_is_detected_by_its_postback() {
+        assert!(is_login_schema(SCHEMA));
+        assert!(!is_login_schema(
+            "<AuthenticateResponse><PostBack>/cgi/login</PostBack></AuthenticateResponse>"
+        ));
+        assert!(!is_login_schema("<html></html>"));
+    }
+}
@@ -0,0 +1,670 @@
+use crate::{
+    io::{detail_and_log, spit_and_log},
+    shutdown::{requested, wait},
+};
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use sysinfo::{
+    get_current_pid, Pid, ProcessRefreshKind, ProcessStatus, ProcessesToUpdate, System, UpdateKind,
+};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::{
+    Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0},
+    System::Threading::{OpenProcess, WaitForSingleObject, PROCESS_SYNCHRONIZE},
+};
+
+/// Citrix Workspace client processes watched by default on this platform
+#[cfg(target_os = "windows")]
+const DEFAULT_CLIENT_PROCESSES: [&str; 1] = ["wfica32.exe"];
+#[cfg(target_os = "macos")]
+const DEFAULT_CLIENT_PROCESSES: [&str; 1] = ["Citrix Viewer"];
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const DEFAULT_CLIENT_PROCESSES: [&str; 1] = ["wfica"];
+
+/// Longest single wait on the client, so stop requests are still noticed quickly
+const WAIT_STEP: Duration = Duration::from_millis(500);
+/// How often an untracked (name matched) client is checked again
+const UNTRACKED_INTERVAL: Duration = Duration::from_secs(1);
+/// Deepest ancestor chain followed when attributing a client to this process
+const MAX_ANCESTORS: usize = 64;
+
+/// Get the default client process names for this platform
+/// - Windows: wfica32.exe
+/// - macOS: Citrix Viewer
+/// - Linux and others: wfica
+pub fn default_client_processes() -> Vec<String> {
+    DEFAULT_CLIENT_PROCESSES
+        .iter()
+        .map(|p| p.to_string())
+        .collect()
+}
+
+/// Check if a process name is one of the watched names
+/// - Names are compared without regard to case, as macOS and Windows don't care either
+pub fn is_watched(name: &str, watched: &[String]) -> bool {
+    watched.iter().any(|w| name.eq_ignore_ascii_case(w))
+}
+
+/// How a client process relates to the session we launched
+#[derive(Debug, PartialEq)]
+enum Attribution {
+    Ours,    // Opened our ICA file, or descends from this process
+    Unknown, // No ICA file to go by (i.e. macOS LaunchServices, wfcrun32 or no permission)
+    Foreign, // Opened some other ICA file, i.e. a technician's app
+}
+
+/// Watches the Citrix client process running the session we launched
+/// - Other client processes (i.e. a second app opened by a technician) are ignored
+/// - Falls back to name matching only when a process can't be attributed
+pub struct SessionMonitor {
+    system: System,
+    ica_file: PathBuf, // Full path of the ICA file we launch, matched against command lines
+    own_pid: Option<Pid>, // This process, matched against client ancestors
+    tracked: Option<Pid>, // Client process attributed to our session
+    exit_handle: Option<ExitHandle>, // Signalled when the tracked process exits, if supported
+}
+
+impl SessionMonitor {
+    /// Create a monitor for sessions launched from the given ICA file
+    /// - A relative file name is taken as relative to the working directory, where it is downloaded
+    pub fn new(ica_file: &str) -> SessionMonitor {
+        let ica_file = match env::current_dir() {
+            Ok(d) => d.join(ica_file),
+            Err(_) => PathBuf::from(ica_file),
+        };
+        SessionMonitor {
+            system: System::new(),
+            ica_file,
+            own_pid: get_current_pid().ok(),
+            tracked: None,
+            exit_handle: None,
+        }
+    }
+
+    /// Check if the client process for our session is running
+    /// - Keeps watching the tracked PID while it lives
+    /// - Otherwise looks for a watched process attributed to our session and tracks it
+    /// - Returns true for unattributable watched processes, false for foreign ones
+    pub fn is_running(&mut self, watched: &[String]) -> bool {
+        if let Some(pid) = self.tracked {
+            // Only the tracked process needs refreshing while it lives
+            self.system.refresh_processes_specifics(
+                ProcessesToUpdate::Some(&[pid]),
+                true,
+                ProcessRefreshKind::new(),
+            );
+            match self.system.process(pid) {
+                Some(p)
+                    if p.status() != ProcessStatus::Zombie
+                        && is_watched(&p.name().to_string_lossy(), watched) =>
+                {
+                    return true
+                }
+                _ => {
+                    spit_and_log(&format!("Client process {} has exited.", pid));
+                    self.tracked = None;
+                    self.exit_handle = None;
+                }
+            }
+        }
+        // Refresh the process list with command lines only, not CPU, memory or disks
+        self.system.refresh_processes_specifics(
+            ProcessesToUpdate::All,
+            true,
+            ProcessRefreshKind::new().with_cmd(UpdateKind::OnlyIfNotSet),
+        );
+        let (client, unknown) = find_client(&self.system, watched, self.own_pid, &self.ica_file);
+        if let Some(pid) = client {
+            detail_and_log(&format!("Tracking client process {}.", pid));
+            self.tracked = Some(pid);
+            self.exit_handle = ExitHandle::open(pid);
+            return true;
+        }
+        unknown
+    }
+
+    /// Wait for the tracked client process to exit
+    /// - Wakes as soon as the process exits (pidfd on Linux, process handle on Windows)
+    /// - Elsewhere, checks only the tracked PID every half second
+    /// - Without a tracked process, waits at most one second so name matches are rechecked
+    /// - Wakes early if the process is asked to stop
+    /// - Returns true if the tracked process has exited
+    pub fn wait_for_exit(&mut self, timeout: Duration) -> bool {
+        let pid = match self.tracked {
+            Some(p) => p,
+            None => {
+                wait(timeout.min(UNTRACKED_INTERVAL));
+                return false;
+            }
+        };
+        let start = Instant::now();
+        while !requested() && start.elapsed() < timeout {
+            let step = WAIT_STEP.min(timeout.saturating_sub(start.elapsed()));
+            let exited = match &self.exit_handle {
+                Some(h) => h.wait(step),
+                None => {
+                    wait(step);
+                    self.system.refresh_processes_specifics(
+                        ProcessesToUpdate::Some(&[pid]),
+                        true,
+                        ProcessRefreshKind::new(),
+                    );
+                    match self.system.process(pid) {
+                        Some(p) => p.status() == ProcessStatus::Zombie,
+                        None => true,
+                    }
+                }
+            };
+            if exited {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Read-only view of the process table
+/// - Implemented by sysinfo's System; tests use a hand-built table
+trait ProcessTable {
+    /// Get every process ID in the table
+    fn pids(&self) -> Vec<Pid>;
+    /// Get a process name, or an empty String if it has gone
+    fn name(&self, pid: Pid) -> String;
+    /// Check if a process exists and has not exited (zombies have)
+    fn is_alive(&self, pid: Pid) -> bool;
+    /// Get the parent of a process, if known
+    fn parent(&self, pid: Pid) -> Option<Pid>;
+    /// Get a process command line, program first (empty if unknown)
+    fn cmd(&self, pid: Pid) -> Vec<OsString>;
+}
+
+impl ProcessTable for System {
+    fn pids(&self) -> Vec<Pid> {
+        self.processes().keys().copied().collect()
+    }
+    fn name(&self, pid: Pid) -> String {
+        match self.process(pid) {
+            Some(p) => p.name().to_string_lossy().to_string(),
+            None => String::new(),
+        }
+    }
+    fn is_alive(&self, pid: Pid) -> bool {
+        match self.process(pid) {
+            Some(p) => p.status() != ProcessStatus::Zombie,
+            None => false,
+        }
+    }
+    fn parent(&self, pid: Pid) -> Option<Pid> {
+        self.process(pid).and_then(|p| p.parent())
+    }
+    fn cmd(&self, pid: Pid) -> Vec<OsString> {
+        match self.process(pid) {
+            Some(p) => p.cmd().to_vec(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Find the client process running the session we launched
+/// - Accepts the process table, watched names, this process's PID and our ICA file's full path
+/// - Returns the first watched process attributed to our session, if any, and whether
+///   any watched process could not be attributed either way
+fn find_client(
+    table: &impl ProcessTable,
+    watched: &[String],
+    own_pid: Option<Pid>,
+    ica_file: &Path,
+) -> (Option<Pid>, bool) {
+    let mut unknown = false;
+    for pid in table.pids() {
+        if !is_watched(&table.name(pid), watched) || !table.is_alive(pid) {
+            continue;
+        }
+        match attribute(table, pid, own_pid, ica_file) {
+            Attribution::Ours => return (Some(pid), unknown),
+            Attribution::Unknown => unknown = true,
+            Attribution::Foreign => (),
+        }
+    }
+    (None, unknown)
+}
+
+/// Work out whether a client process belongs to the session we launched
+/// - A process started by this one (directly or through a handler) is ours
+/// - A process with our ICA file on its command line is ours
+/// - A process with some other ICA file on its command line is foreign
+/// - Anything else is unknown, i.e. a client started by a handler which has since exited
+/// - Ancestors are followed at most MAX_ANCESTORS deep, in case of PID reuse loops
+fn attribute(
+    table: &impl ProcessTable,
+    pid: Pid,
+    own_pid: Option<Pid>,
+    ica_file: &Path,
+) -> Attribution {
+    let mut parent = table.parent(pid);
+    for _ in 0..MAX_ANCESTORS {
+        match parent {
+            Some(p) if Some(p) == own_pid => return Attribution::Ours,
+            Some(p) => parent = table.parent(p),
+            None => break,
+        }
+    }
+    let cmd = table.cmd(pid);
+    let args = match cmd.as_slice() {
+        [] | [_] => return Attribution::Unknown,
+        [_, args @ ..] => args,
+    };
+    if args.iter().any(|a| is_our_ica_file(a, ica_file)) {
+        return Attribution::Ours;
+    }
+    match args.iter().any(is_ica_file) {
+        true => Attribution::Foreign,
+        false => Attribution::Unknown,
+    }
+}
+
+/// Check if a command line argument names an ICA file
+fn is_ica_file(arg: &OsString) -> bool {
+    let arg = arg.to_string_lossy();
+    Path::new(arg.trim_matches('"'))
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("ica"))
+}
+
+/// Check if a command line argument names our ICA file
+/// - Compares full paths, so an ICA file of the same name elsewhere isn't ours
+/// - A relative path is taken as relative to our ICA file's directory, i.e. our working directory
+/// - Case is ignored on Windows and macOS, whose file systems ignore it too
+fn is_our_ica_file(arg: &OsString, ica_file: &Path) -> bool {
+    let arg = arg.to_string_lossy();
+    let path = match ica_file.parent() {
+        Some(d) => d.join(arg.trim_matches('"')),
+        None => PathBuf::from(arg.trim_matches('"')),
+    };
+    match cfg!(any(target_os = "windows", target_os = "macos")) {
+        true => {
+            let lower = |p: &Path| PathBuf::from(p.to_string_lossy().to_lowercase());
+            lower(&path) == lower(ica_file)
+        }
+        false => path == ica_file,
+    }
+}
+
+/*******************
+ * Exit Handles *
+ ******************/
+
+/// Handle which becomes ready when a process exits (Linux pidfd)
+#[cfg(target_os = "linux")]
+struct ExitHandle(OwnedFd);
+
+#[cfg(target_os = "linux")]
+impl ExitHandle {
+    /// Open a pidfd for the process - returns None if the kernel doesn't support it
+    fn open(pid: Pid) -> Option<ExitHandle> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_u32() as libc::pid_t, 0) };
+        match fd < 0 {
+            true => None,
+            false => Some(ExitHandle(unsafe {
+                OwnedFd::from_raw_fd(fd as libc::c_int)
+            })),
+        }
+    }
+
+    /// Block until the process exits or the timeout passes - returns true if it exited
+    fn wait(&self, timeout: Duration) -> bool {
+        let mut fds = libc::pollfd {
+            fd: self.0.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) > 0 }
+    }
+}
+
+/// Handle which becomes ready when a process exits (Windows process handle)
+#[cfg(target_os = "windows")]
+struct ExitHandle(HANDLE);
+
+#[cfg(target_os = "windows")]
+impl ExitHandle {
+    /// Open the process for waiting - returns None if access is denied
+    fn open(pid: Pid) -> Option<ExitHandle> {
+        let handle = unsafe { OpenProcess(PROCESS_SYNCHRONIZE, 0, pid.as_u32()) };
+        match handle.is_null() {
+            true => None,
+            false => Some(ExitHandle(handle)),
+        }
+    }
+
+    /// Block until the process exits or the timeout passes - returns true if it exited
+    fn wait(&self, timeout: Duration) -> bool {
+        unsafe { WaitForSingleObject(self.0, timeout.as_millis() as u32) == WAIT_OBJECT_0 }
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl Drop for ExitHandle {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// No exit handle on other platforms; the tracked PID is checked instead
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+struct ExitHandle;
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+impl ExitHandle {
+    fn open(_pid: Pid) -> Option<ExitHandle> {
+        None
+    }
+
+    fn wait(&self, _timeout: Duration) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// Process in a hand-built process table
+    struct FakeProcess {
+        name: &'static str,
+        parent: Option<usize>,
+        cmd: Vec<&'static str>,
+        zombie: bool,
+    }
+
+    /// Hand-built process table, by PID
+    #[derive(Default)]
+    struct FakeTable(BTreeMap<usize, FakeProcess>);
+
+    impl FakeTable {
+        fn with(
+            mut self,
+            pid: usize,
+            name: &'static str,
+            parent: Option<usize>,
+            cmd: &[&'static str],
+        ) -> Self {
+            let cmd = cmd.to_vec();
+            let zombie = false;
+            self.0.insert(
+                pid,
+                FakeProcess {
+                    name,
+                    parent,
+                    cmd,
+                    zombie,
+                },
+            );
+            self
+        }
+        fn zombie(mut self, pid: usize) -> Self {
+            if let Some(p) = self.0.get_mut(&pid) {
+                p.zombie = true;
+            }
+            self
+        }
+        fn get(&self, pid: Pid) -> Option<&FakeProcess> {
+            self.0.get(&(pid.as_u32() as usize))
+        }
+    }
+
+    impl ProcessTable for FakeTable {
+        fn pids(&self) -> Vec<Pid> {
+            self.0.keys().map(|p| Pid::from(*p)).collect()
+        }
+        fn name(&self, pid: Pid) -> String {
+            self.get(pid)
+                .map(|p| p.name.to_string())
+                .unwrap_or_default()
+        }
+        fn is_alive(&self, pid: Pid) -> bool {
+            self.get(pid).is_some_and(|p| !p.zombie)
+        }
+        fn parent(&self, pid: Pid) -> Option<Pid> {
+            self.get(pid).and_then(|p| p.parent).map(Pid::from)
+        }
+        fn cmd(&self, pid: Pid) -> Vec<OsString> {
+            match self.get(pid) {
+                Some(p) => p.cmd.iter().map(OsString::from).collect(),
+                None => Vec::new(),
+            }
+        }
+    }
+
+    const OWN_PID: usize = 100;
+    #[cfg(target_os = "windows")]
+    const ICA: &str = "C:\\Kiosk\\AutoLaunch.ica";
+    #[cfg(not(target_os = "windows"))]
+    const ICA: &str = "/home/kiosk/AutoLaunch.ica";
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn find(table: &FakeTable, watched: &[String]) -> (Option<Pid>, bool) {
+        find_client(table, watched, Some(Pid::from(OWN_PID)), Path::new(ICA))
+    }
+
+    #[test]
+    fn default_processes_match_the_platform() {
+        #[cfg(target_os = "windows")]
+        let expected = "wfica32.exe";
+        #[cfg(target_os = "macos")]
+        let expected = "Citrix Viewer";
+        #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+        let expected = "wfica";
+        assert_eq!(default_client_processes(), [expected]);
+        let table = FakeTable::default().with(200, expected, Some(OWN_PID), &[expected]);
+        assert_eq!(
+            find(&table, &default_client_processes()).0,
+            Some(Pid::from(200))
+        );
+    }
+
+    #[test]
+    fn each_platform_client_is_matched_when_watched() {
+        for client in ["wfica", "Citrix Viewer", "wfica32.exe"] {
+            let table = FakeTable::default().with(200, client, Some(OWN_PID), &[client]);
+            assert_eq!(
+                find(&table, &names(&[client])).0,
+                Some(Pid::from(200)),
+                "{}",
+                client
+            );
+        }
+    }
+
+    #[test]
+    fn names_match_whole_and_without_case() {
+        let watched = names(&["wfica32.exe", "CDViewer"]);
+        assert!(is_watched("WFICA32.EXE", &watched));
+        assert!(is_watched("cdviewer", &watched));
+        assert!(!is_watched("wfica", &watched));
+        assert!(!is_watched("wfica32.exe.bak", &watched));
+        assert!(!is_watched("wfica32.exe", &[]));
+    }
+
+    #[test]
+    fn configured_names_replace_the_defaults() {
+        let table = FakeTable::default()
+            .with(200, "wfica", Some(OWN_PID), &["wfica"])
+            .with(201, "CDViewer", Some(OWN_PID), &["CDViewer"]);
+        assert_eq!(find(&table, &names(&["CDViewer"])).0, Some(Pid::from(201)));
+        assert_eq!(find(&table, &names(&["Other"])), (None, false));
+    }
+
+    #[test]
+    fn descendants_of_this_process_are_ours() {
+        // open -> handler -> client, with no arguments to go by
+        let table = FakeTable::default()
+            .with(1, "init", None, &["init"])
+            .with(
+                OWN_PID,
+                "citrix-autolaunch",
+                Some(1),
+                &["citrix-autolaunch"],
+            )
+            .with(150, "xdg-open", Some(OWN_PID), &["xdg-open", ICA])
+            .with(200, "wfica", Some(150), &[]);
+        assert_eq!(
+            attribute(
+                &table,
+                Pid::from(200),
+                Some(Pid::from(OWN_PID)),
+                Path::new(ICA)
+            ),
+            Attribution::Ours
+        );
+    }
+
+    #[test]
+    fn clients_opening_our_ica_file_are_ours() {
+        for arg in [
+            "/home/kiosk/AutoLaunch.ica",
+            "\"/home/kiosk/AutoLaunch.ica\"",
+            "/home/kiosk/./AutoLaunch.ica",
+            "AutoLaunch.ica",
+        ] {
+            let table = FakeTable::default().with(200, "wfica", Some(1), &["wfica", "-quiet", arg]);
+            assert_eq!(
+                attribute(
+                    &table,
+                    Pid::from(200),
+                    Some(Pid::from(OWN_PID)),
+                    Path::new(ICA)
+                ),
+                Attribution::Ours,
+                "{}",
+                arg
+            );
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn clients_opening_our_ica_file_by_windows_path_are_ours() {
+        let table = FakeTable::default().with(
+            200,
+            "wfica32.exe",
+            Some(1),
+            &["wfica32.exe", "\"c:\\kiosk\\autolaunch.ica\""],
+        );
+        assert_eq!(
+            attribute(
+                &table,
+                Pid::from(200),
+                Some(Pid::from(OWN_PID)),
+                Path::new(ICA)
+            ),
+            Attribution::Ours
+        );
+    }
+
+    #[test]
+    fn clients_without_an_ica_file_are_unknown() {
+        // wfcrun32 starts wfica32 with its own arguments and exits, so the ancestry is lost
+        let table = FakeTable::default().with(
+            200,
+            "wfica32.exe",
+            Some(150),
+            &["wfica32.exe", "-Embedding", "/silent"],
+        );
+        assert_eq!(
+            attribute(
+                &table,
+                Pid::from(200),
+                Some(Pid::from(OWN_PID)),
+                Path::new(ICA)
+            ),
+            Attribution::Unknown
+        );
+        assert_eq!(find(&table, &names(&["wfica32.exe"])), (None, true));
+    }
+
+    #[test]
+    fn clients_without_arguments_are_unknown() {
+        let table = FakeTable::default()
+            .with(200, "Citrix Viewer", Some(1), &["Citrix Viewer"])
+            .with(201, "Citrix Viewer", Some(1), &[]);
+        for pid in [200, 201] {
+            assert_eq!(
+                attribute(
+                    &table,
+                    Pid::from(pid),
+                    Some(Pid::from(OWN_PID)),
+                    Path::new(ICA)
+                ),
+                Attribution::Unknown
+            );
+        }
+        assert_eq!(find(&table, &names(&["Citrix Viewer"])), (None, true));
+    }
+
+    #[test]
+    fn clients_opening_another_ica_file_are_foreign() {
+        // Including one of the same name in another directory
+        for arg in [
+            "/tmp/Technician.ica",
+            "/tmp/AutoLaunch.ica",
+            "\"/tmp/x.ICA\"",
+        ] {
+            let table = FakeTable::default().with(200, "wfica", Some(1), &["wfica", arg]);
+            assert_eq!(
+                attribute(
+                    &table,
+                    Pid::from(200),
+                    Some(Pid::from(OWN_PID)),
+                    Path::new(ICA)
+                ),
+                Attribution::Foreign,
+                "{}",
+                arg
+            );
+            assert_eq!(find(&table, &names(&["wfica"])), (None, false));
+        }
+    }
+
+    #[test]
+    fn our_client_is_found_among_foreign_ones() {
+        let table = FakeTable::default()
+            .with(200, "wfica", Some(1), &["wfica", "/tmp/Technician.ica"])
+            .with(300, "wfica", Some(1), &["wfica", ICA]);
+        assert_eq!(find(&table, &names(&["wfica"])).0, Some(Pid::from(300)));
+    }
+
+    #[test]
+    fn exited_clients_are_ignored() {
+        let table = FakeTable::default()
+            .with(200, "wfica", Some(OWN_PID), &["wfica", ICA])
+            .zombie(200);
+        assert_eq!(find(&table, &names(&["wfica"])), (None, false));
+    }
+
+    #[test]
+    fn parent_loops_end() {
+        let table = FakeTable::default()
+            .with(200, "wfica", Some(201), &["wfica", "/tmp/Other.ica"])
+            .with(201, "sh", Some(200), &["sh"]);
+        assert_eq!(
+            attribute(
+                &table,
+                Pid::from(200),
+                Some(Pid::from(OWN_PID)),
+                Path::new(ICA)
+            ),
+            Attribution::Foreign
+        );
+    }
+}
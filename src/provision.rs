@@ -0,0 +1,132 @@
+use crate::storage::{save_settings, set_field, AuthMethod, Settings};
+use std::{env, fs};
+
+/// Prefix of environment variables holding settings, i.e. CITRIX_AUTOLAUNCH_BASE_URI
+const ENV_PREFIX: &str = "CITRIX_AUTOLAUNCH_";
+
+/// Read settings from a provisioning file
+/// - One `field=value` per line, with fields named as for the set command
+/// - Blank lines and lines starting with # are skipped
+/// - Returns an error String if the file is unreadable or a line has no `=`
+pub fn file_values(path: &str) -> Result<Vec<(String, String)>, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => return Err(format!("Failed to read provisioning file: {:?}", e)),
+    };
+    let mut values = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((field, value)) => values.push((field.trim().to_string(), value.to_string())),
+            None => {
+                return Err(format!(
+                    "Line {} of the provisioning file is not field=value",
+                    number + 1
+                ))
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Read settings from CITRIX_AUTOLAUNCH_<FIELD> environment variables
+/// - CITRIX_AUTOLAUNCH_NFACTOR_<ID> sets an nFactor field, with the ID exactly as written
+///   (i.e. CITRIX_AUTOLAUNCH_NFACTOR_otp sets nfactor.otp), since IDs are case-sensitive
+/// - CITRIX_AUTOLAUNCH_LAUNCHER_ENVIRONMENT_<NAME> sets a launcher environment variable, also as written
+/// - CITRIX_AUTOLAUNCH_CREDENTIAL_SOURCE_<FIELD> sets where that secret is read from at logon
+pub fn environment_values() -> Vec<(String, String)> {
+    env::vars()
+        .filter_map(|(name, value)| {
+            let name = name.strip_prefix(ENV_PREFIX)?;
+            let (source, name) = match name.strip_prefix("CREDENTIAL_SOURCE_") {
+                Some(n) => ("credential_source.", n),
+                None => ("", name),
+            };
+            let field = match (
+                name.strip_prefix("NFACTOR_"),
+                name.strip_prefix("LAUNCHER_ENVIRONMENT_"),
+            ) {
+                (Some(id), _) => format!("nfactor.{}", id),
+                (_, Some(variable)) => format!("launcher_environment.{}", variable),
+                _ => name.to_lowercase(),
+            };
+            Some((format!("{}{}", source, field), value))
+        })
+        .collect()
+}
+
+/// Create and save Settings from provisioned values without prompting
+/// - Later values win, so pass file values, then environment values, then flags
+/// - Each value is validated as it is set
+/// - Returns an error naming any required values that are missing
+pub fn provision(values: &[(String, String)]) -> Result<Settings, String> {
+    let mut settings = Settings::default();
+    // The launcher command must be set before its directory and environment
+    let (launcher_options, fields): (Vec<_>, Vec<_>) = values
+        .iter()
+        .partition(|(field, _)| field.starts_with("launcher_"));
+    for (field, value) in fields.into_iter().chain(launcher_options) {
+        if let Err(e) = set_field(&mut settings, field, value) {
+            return Err(format!("Invalid value for {}: {}", field, e));
+        }
+    }
+    let mut missing = Vec::new();
+    if settings.base_uri.is_empty() {
+        missing.push("base_uri");
+    }
+    if settings.application_name.is_empty() {
+        missing.push("application_name");
+    }
+    if settings.auth_method == AuthMethod::ExplicitForms {
+        if settings.login.is_empty() {
+            missing.push("login");
+        }
+        if settings.passwd.is_empty() && !settings.credential_sources.contains_key("passwd") {
+            missing.push("passwd");
+        }
+    }
+    if !missing.is_empty() {
+        return Err(format!(
+            "Missing required settings: {} (set auth_method to negotiate or anonymous to skip credentials)",
+            missing.join(", ")
+        ));
+    }
+    if !settings.is_valid() {
+        return Err("Settings are invalid".to_string());
+    }
+    save_settings(&settings)?;
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_names_keep_the_case_of_ids_and_variables() {
+        // Unique names, as tests share the process environment
+        env::set_var("CITRIX_AUTOLAUNCH_NFACTOR_otpCaseTest", "123456");
+        env::set_var("CITRIX_AUTOLAUNCH_LAUNCHER_ENVIRONMENT_TokenCaseTest", "t");
+        env::set_var(
+            "CITRIX_AUTOLAUNCH_CREDENTIAL_SOURCE_NFACTOR_pinCaseTest",
+            "env:PIN",
+        );
+        let values = environment_values();
+        for expected in [
+            ("nfactor.otpCaseTest", "123456"),
+            ("launcher_environment.TokenCaseTest", "t"),
+            ("credential_source.nfactor.pinCaseTest", "env:PIN"),
+        ] {
+            assert!(
+                values
+                    .iter()
+                    .any(|(f, v)| (f.as_str(), v.as_str()) == expected),
+                "{:?}",
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::io::spit_and_log;
+use std::{
+    process::exit,
+    sync::atomic::{AtomicBool, Ordering},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// Set once the process has been asked to stop
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Set while a web session is open, so the main loop must log off before stopping
+static CLEANUP_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Register handlers for Ctrl+C and termination requests (SIGINT, SIGTERM, SIGHUP)
+/// - While a web session is open, handlers only set a flag and the main loop does the cleanup
+/// - Otherwise (i.e. at a prompt or while logging in), or on a second request, exits at once
+/// - Returns an error String if the handlers cannot be registered
+pub fn install() -> Result<(), String> {
+    let handler = || {
+        if STOP_REQUESTED.swap(true, Ordering::SeqCst) || !CLEANUP_PENDING.load(Ordering::SeqCst) {
+            spit_and_log("Stopped.");
+            exit(0);
+        }
+    };
+    match ctrlc::set_handler(handler) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Failed to register stop handler: {}", e)),
+    }
+}
+
+/// Tell the stop handler whether a web session is open that must be logged off first
+pub fn set_cleanup_pending(pending: bool) {
+    CLEANUP_PENDING.store(pending, Ordering::SeqCst);
+}
+
+/// Check if the process has been asked to stop
+pub fn requested() -> bool {
+    STOP_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Sleep for the given duration, waking early if the process is asked to stop
+pub fn wait(duration: Duration) {
+    let start = Instant::now();
+    while !requested() && start.elapsed() < duration {
+        sleep(Duration::from_millis(100).min(duration.saturating_sub(start.elapsed())));
+    }
+}
@@ -1,17 +1,116 @@
 use crate::{
+    citrix::{logon, StoreSession},
     crypto::{decrypt_string, encrypt_string},
-    io::{input, pw_input, spit_and_log},
+    io::{input, is_interactive, log_to_file, pw_input, spit, spit_and_log},
+    launch::launch_file,
+    maximize::window_titles,
+    migrate::{migrate, NEWER_SCHEMA, SCHEMA_VERSION},
+    process::default_client_processes,
 };
+use gethostname::gethostname;
 use reqwest::{blocking, Url};
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, to_string};
-use std::{fs, path::Path};
+use serde_json::{from_str, from_value, to_string, to_string_pretty, to_value, Map, Value};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+#[cfg(target_os = "windows")]
+use std::{iter::once, mem::size_of, os::windows::ffi::OsStrExt, ptr::null_mut};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::{
+    Foundation::{CloseHandle, ERROR_SUCCESS, GENERIC_ALL, HANDLE},
+    Security::Authorization::{SetNamedSecurityInfoW, SE_FILE_OBJECT},
+    Security::{
+        AddAccessAllowedAce, GetLengthSid, GetTokenInformation, InitializeAcl, TokenUser,
+        ACCESS_ALLOWED_ACE, ACL, ACL_REVISION, DACL_SECURITY_INFORMATION,
+        PROTECTED_DACL_SECURITY_INFORMATION, TOKEN_QUERY, TOKEN_USER,
+    },
+    System::Threading::{GetCurrentProcess, OpenProcessToken},
+};
 
-// TODO: Allow user to modify location and name of settings file
+/// Name of the settings file in each directory searched
 const SETTINGS_FILE: &str = "settings.txt";
+/// Suffix of the encrypted secrets file kept beside the settings file
+const SECRETS_SUFFIX: &str = ".secrets";
+/// Suffix of the backup kept of the previous good settings and secrets files
+const BACKUP_SUFFIX: &str = ".bak";
+/// Suffix of the temporary file written before replacing a settings or secrets file
+const TEMP_SUFFIX: &str = ".tmp";
+/// Suffix a damaged settings or secrets file is renamed with before new settings replace it
+const DAMAGED_SUFFIX: &str = ".damaged";
+/// Settings fields kept out of the readable settings file, in the encrypted secrets file
+const SECRET_FIELDS: [&str; 2] = ["passwd", "nfactor_fields"];
+/// Directory holding the settings file under the user and system config directories
+const CONFIG_DIR: &str = "citrix-autolaunch";
+/// Settings file path chosen on the command line, if any
+static SETTINGS_PATH: OnceLock<PathBuf> = OnceLock::new();
+/// Profile chosen on the command line, if any
+static PROFILE: OnceLock<String> = OnceLock::new();
+/// Profile created when the settings file holds none yet
+const DEFAULT_PROFILE: &str = "default";
+/// Reported when settings are needed but there is no terminal to prompt at
+const CANNOT_PROMPT: &str = "Settings required, but there is no terminal to prompt for them. Provision them with the provision command or copy a settings file in place.";
+/// Number of times invalid settings are prompted for before giving up
+const MAX_SETUP_ATTEMPTS: usize = 3;
+/// Shown in place of secrets when settings are displayed
+const MASK: &str = "********";
+/// Client name template used when none is configured
+const DEFAULT_CLIENT_NAME: &str = "{hostname}";
 
-/// User entered settings for the application
-#[derive(Deserialize, Serialize)]
+/// Method used to authenticate to StoreFront
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
+pub enum AuthMethod {
+    #[default]
+    ExplicitForms, // Login and password entered through the gateway
+    Negotiate, // Kerberos/SPNEGO pass-through using the machine's credential cache
+    Anonymous, // Unauthenticated store; no credentials at all
+}
+
+/// What to do with the user's existing sessions when the app starts
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
+pub enum SessionCleanup {
+    #[default]
+    None, // Leave existing sessions alone
+    Disconnect, // Disconnect active sessions (they can be reconnected)
+    Logoff,     // Log off every session for a clean start
+}
+
+/// Program used to open the downloaded ICA file
+#[derive(Clone, Default, Deserialize, PartialEq, Serialize)]
+pub enum Launcher {
+    #[default]
+    Default, // Desktop default handler for .ica files
+    Command(LaunchCommand), // Explicit command, i.e. /opt/Citrix/ICAClient/wfica {ica}
+}
+
+/// Explicit command used to launch the ICA file
+#[derive(Clone, Default, Deserialize, PartialEq, Serialize)]
+pub struct LaunchCommand {
+    pub template: String, // Command line with an {ica} placeholder for the ICA file path
+    pub environment: HashMap<String, String>, // Extra environment variables for the command
+    pub working_directory: String, // Directory to run in (blank for the current directory)
+}
+
+/// Where a secret is read from at logon, instead of the secrets file
+/// - The value read is used for that logon only and never written to disk
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+pub enum CredentialSource {
+    Environment(String), // Name of the environment variable holding the secret
+    File(String),        // Path of a file holding the secret, i.e. a systemd credential
+    Command(String),     // Command line printing the secret, i.e. a password manager CLI
+}
+
+/// User entered settings for the application (one profile)
+/// - Fields missing from a stored file take their defaults, so new fields never break old files
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Settings {
     pub base_uri: String,
     pub application_name: String,
@@ -19,6 +118,218 @@ pub struct Settings {
     pub passwd: String,
     pub target: String,
     pub maximization_active: bool,
+    pub nfactor_fields: HashMap<String, String>, // Extra nFactor fields by ID (i.e. passwd1, otp)
+    pub auth_method: AuthMethod,
+    pub session_cleanup: SessionCleanup,
+    pub launch_arguments: String, // Command line passed to the published application
+    pub client_name: String,      // Client name template, i.e. KIOSK-{hostname}
+    pub client_processes: Vec<String>, // Client process names to watch (blank for platform default)
+    pub launcher: Launcher,
+    pub credential_sources: BTreeMap<String, CredentialSource>, // Sources by field (passwd, nfactor.<ID>)
+}
+
+/// Every saved profile, as stored (encrypted) in the settings file
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct SettingsStore {
+    pub schema_version: u32, // Layout version of the stored file (see migrate)
+    pub default_profile: String, // Profile used when none is given with --profile
+    pub profiles: BTreeMap<String, Settings>,
+}
+
+/// Create an empty SettingsStore
+impl Default for SettingsStore {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            default_profile: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl SettingsStore {
+    /// Get the name of the profile in use
+    /// - Uses the profile given with --profile, otherwise the default profile
+    pub fn active_profile(&self) -> String {
+        selected_profile().unwrap_or_else(|| self.default_profile.clone())
+    }
+}
+
+/// Use a named profile instead of the default one
+/// - Only the first call has any effect; call before settings are loaded
+pub fn set_profile(name: &str) {
+    let _ = PROFILE.set(name.to_string());
+}
+
+/// Get the profile given with --profile, if any
+pub fn selected_profile() -> Option<String> {
+    PROFILE.get().cloned()
+}
+
+/// Use a specific settings file instead of searching for one
+/// - Only the first call has any effect; call before settings are loaded
+pub fn set_settings_path(path: &str) {
+    let _ = SETTINGS_PATH.set(PathBuf::from(path));
+}
+
+/// Get the path of the settings file in use
+/// - Uses the path given on the command line, if any
+/// - Otherwise uses the first existing file in search order (see settings_candidates)
+/// - If none exists, new settings go to the per-user config directory
+/// - Searched on every call, so a file put in place later is found
+pub fn settings_path() -> PathBuf {
+    if let Some(p) = SETTINGS_PATH.get() {
+        return p.clone();
+    }
+    let candidates = settings_candidates();
+    match candidates.iter().find(|p| p.exists()) {
+        Some(p) => p.clone(),
+        None => match candidates.into_iter().next() {
+            Some(p) => p,
+            None => PathBuf::from(SETTINGS_FILE),
+        },
+    }
+}
+
+/// Move the settings that earlier versions kept in the working directory to where new settings go
+/// - Called by load_store when no settings file is found in search order, so it runs once
+/// - Moves the secrets file and both backups with the settings file
+/// - Moves nothing if any of them is already at the target
+/// - Copies, then deletes the originals, since the config directory may be on another drive
+/// - On failure, logs why and leaves the files where they were
+fn migrate_legacy_settings(target: &Path) {
+    let legacy = Path::new(SETTINGS_FILE);
+    if !legacy.is_file() {
+        return;
+    }
+    migrate_secrets_name(legacy);
+    let moves: Vec<(PathBuf, PathBuf)> = [
+        (legacy.to_path_buf(), target.to_path_buf()),
+        (
+            with_suffix(legacy, SECRETS_SUFFIX),
+            with_suffix(target, SECRETS_SUFFIX),
+        ),
+    ]
+    .into_iter()
+    .flat_map(|(from, to)| [(backup_path(&from), backup_path(&to)), (from, to)])
+    .filter(|(from, _)| from.exists())
+    .collect();
+    if let Some((_, to)) = moves.iter().find(|(_, to)| to.exists()) {
+        spit_and_log(&format!(
+            "Found {} in the working directory, but {} already exists. Move or delete one of them.",
+            SETTINGS_FILE,
+            to.display()
+        ));
+        return;
+    }
+    if let Some(dir) = target.parent().filter(|d| !d.as_os_str().is_empty()) {
+        if let Err(e) = fs::create_dir_all(dir) {
+            spit_and_log(&format!("Failed to create settings directory: {:?}", e));
+            return;
+        }
+    }
+    for (i, (from, to)) in moves.iter().enumerate() {
+        let copied = match fs::read(from) {
+            Ok(contents) => write_atomic(to, &contents),
+            Err(e) => Err(format!("Failed to read {}: {:?}", from.display(), e)),
+        };
+        if let Err(e) = copied {
+            // Undo the copies made so far, so the settings stay whole in one place
+            for (_, to) in &moves[..i] {
+                let _ = fs::remove_file(to);
+            }
+            spit_and_log(&format!(
+                "Error: {}\r\n\r\nFailed to move {} from the working directory.",
+                e, SETTINGS_FILE
+            ));
+            return;
+        }
+    }
+    for (from, _) in &moves {
+        if let Err(e) = fs::remove_file(from) {
+            spit_and_log(&format!(
+                "Copied {} to the settings directory, but failed to delete it: {:?}",
+                from.display(),
+                e
+            ));
+        }
+    }
+    spit_and_log(&format!(
+        "Moved {} and the files beside it from the working directory to {}.",
+        SETTINGS_FILE,
+        target.display()
+    ));
+}
+
+/// Get the path of the encrypted secrets file for the settings file in use
+/// - Sits beside the settings file, i.e. settings.txt and settings.txt.secrets
+/// - Named after the whole file name, so a.txt and a.json don't share their secrets
+pub fn secrets_path() -> PathBuf {
+    with_suffix(&settings_path(), SECRETS_SUFFIX)
+}
+
+/// Rename a secrets file named by replacing the settings file's extension, as earlier versions did
+/// - Only if the settings file has no secrets file of its own yet, so it runs once
+/// - Renames its backup too
+/// - On failure, logs why; the secrets are then blank until set again
+fn migrate_secrets_name(path: &Path) {
+    let (old, new) = (
+        path.with_extension("secrets"),
+        with_suffix(path, SECRETS_SUFFIX),
+    );
+    if old == path || !old.is_file() || new.exists() {
+        return;
+    }
+    for (from, to) in [(backup_path(&old), backup_path(&new)), (old, new)] {
+        if !from.exists() {
+            continue;
+        }
+        match fs::rename(&from, &to) {
+            Ok(_) => log_to_file(&format!("Renamed {} to {}", from.display(), to.display())),
+            Err(e) => spit_and_log(&format!(
+                "Error: {:?}\r\n\r\nFailed to rename {} to {}.",
+                e,
+                from.display(),
+                to.display()
+            )),
+        }
+    }
+}
+
+/// Get the places searched for a settings file, in order
+/// - Per-user config directory: $XDG_CONFIG_HOME or ~/.config (%APPDATA% on Windows)
+/// - System config directory: /etc (%ProgramData% on Windows)
+/// - The executable's directory, for a copy kept beside a portable executable
+fn settings_candidates() -> Vec<PathBuf> {
+    let user_dir = match cfg!(target_os = "windows") {
+        true => env::var_os("APPDATA").map(PathBuf::from),
+        false => match env::var_os("XDG_CONFIG_HOME") {
+            Some(d) if !d.is_empty() => Some(PathBuf::from(d)),
+            _ => env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")),
+        },
+    };
+    let system_dir = match cfg!(target_os = "windows") {
+        true => env::var_os("ProgramData").map(PathBuf::from),
+        false => Some(PathBuf::from("/etc")),
+    };
+    let executable_dir = env::current_exe()
+        .ok()
+        .and_then(|e| e.parent().map(|p| p.to_path_buf()));
+    [
+        user_dir.map(|d| d.join(CONFIG_DIR)),
+        system_dir.map(|d| d.join(CONFIG_DIR)),
+        executable_dir,
+    ]
+    .into_iter()
+    .flatten()
+    .map(|d| d.join(SETTINGS_FILE))
+    .collect()
+}
+
+/// Default client name template for Settings
+fn default_client_name() -> String {
+    DEFAULT_CLIENT_NAME.to_string()
 }
 
 /// Create blank copy of Settings struct
@@ -32,6 +343,14 @@ impl Default for Settings {
             passwd: empty.clone(),
             target: empty.clone(),
             maximization_active: false,
+            nfactor_fields: HashMap::new(),
+            auth_method: AuthMethod::default(),
+            session_cleanup: SessionCleanup::default(),
+            launch_arguments: empty.clone(),
+            client_name: default_client_name(),
+            client_processes: Vec::new(),
+            launcher: Launcher::default(),
+            credential_sources: BTreeMap::new(),
         }
     }
 }
@@ -43,19 +362,117 @@ impl Settings {
             Ok(_) => (),
             Err(_) => return false,
         };
-        !self.login.is_empty() || !self.passwd.is_empty()
+        if let Launcher::Command(c) = &self.launcher {
+            if !c.template.contains("{ica}") {
+                return false;
+            }
+        }
+        match self.auth_method {
+            AuthMethod::ExplicitForms => !self.login.is_empty() || !self.passwd.is_empty(),
+            // Pass-through uses the credential cache; unauthenticated stores need nothing
+            AuthMethod::Negotiate | AuthMethod::Anonymous => true,
+        }
+    }
+    /// Get the client name reported to Citrix for this device
+    /// - Replaces {hostname} in the client_name template with the device's host name
+    /// - Falls back to the host name alone if the template is blank
+    pub fn client_name(&self) -> String {
+        let hostname = gethostname().to_string_lossy().to_string();
+        let template = match self.client_name.trim().is_empty() {
+            true => DEFAULT_CLIENT_NAME,
+            false => self.client_name.trim(),
+        };
+        template.replace("{hostname}", &hostname)
+    }
+    /// Get the Citrix client process names to watch for
+    /// - Uses the configured names, or the platform defaults if none are configured
+    pub fn client_processes(&self) -> Vec<String> {
+        match self.client_processes.is_empty() {
+            true => default_client_processes(),
+            false => self.client_processes.clone(),
+        }
+    }
+    /// Get the Settings as readable JSON with secrets masked
+    /// - Masks the password and every nFactor field value
+    pub fn masked(&self) -> Result<String, String> {
+        let mut value = match to_value(self) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to serialize settings: {:?}", e)),
+        };
+        if !self.passwd.is_empty() {
+            value["passwd"] = Value::from(MASK);
+        }
+        if let Some(fields) = value["nfactor_fields"].as_object_mut() {
+            for v in fields.values_mut() {
+                *v = Value::from(MASK);
+            }
+        }
+        match to_string_pretty(&value) {
+            Ok(s) => Ok(s),
+            Err(e) => Err(format!("Failed to serialize settings: {:?}", e)),
+        }
     }
     /// Check if all fields of Settings are blank - returns bool
     pub fn is_empty(&self) -> bool {
-        self.base_uri.is_empty() && self.login.is_empty() && self.passwd.is_empty()
+        self.base_uri.is_empty()
+            && self.login.is_empty()
+            && self.passwd.is_empty()
+            && self.auth_method == AuthMethod::ExplicitForms
     }
 }
 
 /// Create and save Settings from user input
-/// - Validates URL prior to saving
+/// - Fails at once if there is no terminal to prompt at
+/// - Sets a damaged settings file aside first, since the new settings can't be added to it
 fn create_settings(reason: &str) -> Result<Settings, String> {
+    if !is_interactive() {
+        return Err(CANNOT_PROMPT.to_string());
+    }
+    if settings_path().exists() {
+        match load_store() {
+            Err(e) if !e.starts_with(NEWER_SCHEMA) => set_aside_damaged()?,
+            _ => (),
+        }
+    }
+    prompt_settings(reason, 1)
+}
+
+/// Rename the settings file and its secrets file out of the way, i.e. settings.txt.damaged
+/// - Keeps them for inspection rather than deleting them
+/// - Returns an error String if either can't be renamed
+fn set_aside_damaged() -> Result<(), String> {
+    set_aside(&settings_path())?;
+    set_aside(&secrets_path())
+}
+
+/// Rename a damaged settings or secrets file out of the way, i.e. settings.txt.damaged
+/// - Returns Ok if there is no file to rename
+fn set_aside(path: &Path) -> Result<(), String> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let damaged = with_suffix(path, DAMAGED_SUFFIX);
+    if let Err(e) = fs::rename(path, &damaged) {
+        return Err(format!("Failed to set aside {}: {:?}", path.display(), e));
+    }
+    spit_and_log(&format!(
+        "Moved {} to {}.",
+        path.display(),
+        damaged.display()
+    ));
+    Ok(())
+}
+
+/// Prompt for Settings and save them
+/// - Validates URL prior to saving
+/// - Logs in with the URL and credentials before asking for anything else
+/// - Offers the published applications to choose from, and optionally launches the chosen one
+///   so the window to maximize can be picked from the windows that actually open
+/// - Stops with an error String if the input ends or a password can't be read, rather than saving blank answers
+/// - Prompts again on invalid settings or a failed login, up to MAX_SETUP_ATTEMPTS times
+fn prompt_settings(reason: &str, attempt: usize) -> Result<Settings, String> {
     spit_and_log(reason);
-    let base_uri = input("Base URI (https): ");
+    let base_uri = input("Base URI (https): ")?;
     spit_and_log("Verifying gateway...");
     let input_uri = match Url::parse(&base_uri) {
         Ok(u) => u,
@@ -78,24 +495,77 @@ fn create_settings(reason: &str) -> Result<Settings, String> {
         }
         Err(e) => return Err(format!("Failed to connect to gateway: {:?}", e)),
     };
-    let application_name: String = input("Application to launch: ");
-    let login = input("Login: ");
-    let passwd = pw_input("Password: ");
-    let maximization_active = input("Maximize window on launch? (y/n): ") == "y";
-    let target = match maximization_active {
-        true => input("Title of window to maximize: "),
-        false => "".to_string(),
-    };
-    let settings = Settings {
+    let mut auth_method =
+        match input("Use domain pass-through (Kerberos) instead of a password? (y/n): ")? == "y" {
+            true => AuthMethod::Negotiate,
+            false => AuthMethod::ExplicitForms,
+        };
+    let mut login = String::new();
+    let mut passwd = String::new();
+    let mut nfactor_fields = HashMap::new();
+    if auth_method == AuthMethod::ExplicitForms
+        && input("Is this an unauthenticated (anonymous) store? (y/n): ")? == "y"
+    {
+        auth_method = AuthMethod::Anonymous;
+    }
+    if auth_method == AuthMethod::ExplicitForms {
+        login = input("Login: ")?;
+        if login.is_empty() {
+            if attempt >= MAX_SETUP_ATTEMPTS {
+                return Err("No login entered. Giving up.".to_string());
+            }
+            return prompt_settings("No login entered. Please try again.\r\n\r\n", attempt + 1);
+        }
+    }
+    if auth_method == AuthMethod::ExplicitForms {
+        passwd = pw_input("Password: ")?;
+        if input("Does the gateway ask for more than a login and password? (y/n): ")? == "y" {
+            spit_and_log("Enter each extra nFactor field ID as shown in the login schema.");
+            loop {
+                let id = input("nFactor field ID (blank to finish): ")?;
+                if id.is_empty() {
+                    break;
+                }
+                let value = pw_input(&format!("Value for {}: ", id))?;
+                nfactor_fields.insert(id, value);
+            }
+        }
+    }
+    let mut settings = Settings {
         base_uri,
-        application_name,
         login,
         passwd,
-        target,
-        maximization_active,
+        nfactor_fields,
+        auth_method,
+        ..Settings::default()
     };
+    // Test the credentials now, so the applications can be chosen from the store
+    spit_and_log("Logging in...");
+    let session = match logon(&settings) {
+        Ok(s) => s,
+        Err(e) if attempt < MAX_SETUP_ATTEMPTS => {
+            return prompt_settings(
+                &format!(
+                    "Error: {}\r\n\r\nLogin failed. Please try again.\r\n\r\n",
+                    e
+                ),
+                attempt + 1,
+            )
+        }
+        Err(e) => return Err(format!("{} Giving up.", e)),
+    };
+    spit_and_log("Logged in successfully.");
+    let result = prompt_launch_settings(&mut settings, &session);
+    // Log off before saving, whatever the outcome; a failed logoff is only logged
+    if let Err(e) = session.logoff(&settings) {
+        spit_and_log(&format!("Error: {}\r\n\r\nFailed to log off.", e));
+    }
+    result?;
     if !settings.is_valid() {
-        return create_settings("Invalid settings. Please try again.\r\n\r\n");
+        if attempt >= MAX_SETUP_ATTEMPTS {
+            return Err("Settings are still invalid. Giving up.".to_string());
+        }
+        return prompt_settings("Invalid settings. Please try again.\r\n\r\n", attempt + 1);
     }
     match save_settings(&settings) {
         Ok(_) => Ok(settings),
@@ -103,78 +573,812 @@ fn create_settings(reason: &str) -> Result<Settings, String> {
     }
 }
 
+/// Prompt for the application and how to launch it, once logged in
+/// - Fills in the Settings fields not needed to log in
+/// - Returns an error String if no published application was chosen
+fn prompt_launch_settings(settings: &mut Settings, session: &StoreSession) -> Result<(), String> {
+    settings.application_name = choose_application(session)?;
+    settings.launch_arguments = input("Application arguments (leave blank for none): ")?;
+    settings.session_cleanup =
+        match input("End existing sessions on startup? (d = disconnect, l = log off, n = no): ")?
+            .as_str()
+        {
+            "d" => SessionCleanup::Disconnect,
+            "l" => SessionCleanup::Logoff,
+            _ => SessionCleanup::None,
+        };
+    settings.client_name =
+        match input("Client name (use {hostname} for the host name, blank for default): ")? {
+            c if c.is_empty() => default_client_name(),
+            c => c,
+        };
+    settings.client_processes = input(&format!(
+        "Client process names, comma separated (blank for {}): ",
+        default_client_processes().join(", ")
+    ))?
+    .split(',')
+    .map(|p| p.trim().to_string())
+    .filter(|p| !p.is_empty())
+    .collect();
+    settings.launcher = match input(
+        "Launcher command (use {ica} for the ICA file, blank for the default handler): ",
+    )? {
+        t if t.is_empty() => Launcher::Default,
+        template => {
+            let working_directory = input("Working directory (blank for current): ")?;
+            let mut environment = HashMap::new();
+            loop {
+                let variable = input("Environment variable as NAME=value (blank to finish): ")?;
+                match variable.split_once('=') {
+                    Some((name, value)) => {
+                        environment.insert(name.trim().to_string(), value.to_string())
+                    }
+                    None => break,
+                };
+            }
+            Launcher::Command(LaunchCommand {
+                template,
+                environment,
+                working_directory,
+            })
+        }
+    };
+    settings.maximization_active = input("Maximize window on launch? (y/n): ")? == "y";
+    if settings.maximization_active {
+        settings.target = choose_window(settings, session)?;
+    }
+    Ok(())
+}
+
+/// Pick the application to launch from those published to the user
+/// - Accepts the number shown or the exact name
+/// - Asks for the name instead if the store lists no applications
+/// - Returns an error String after MAX_SETUP_ATTEMPTS invalid choices
+fn choose_application(session: &StoreSession) -> Result<String, String> {
+    let names = session.resource_names();
+    if names.is_empty() {
+        spit_and_log("No applications are published to you yet.");
+        return input("Application to launch: ");
+    }
+    spit_and_log("Applications published to you:");
+    for _ in 0..MAX_SETUP_ATTEMPTS {
+        let choice = choose("Application to launch (number or name): ", &names)?;
+        if names.contains(&choice) {
+            return Ok(choice);
+        }
+        spit_and_log(&format!(
+            "{} is not one of the applications listed.",
+            choice
+        ));
+    }
+    Err("No application was chosen. Giving up.".to_string())
+}
+
+/// Pick the title of the window to maximize
+/// - Optionally launches the application once and lists the windows it opened
+/// - Falls back to typing the title if none can be listed (i.e. on this platform)
+/// - Returns an error String if the input ends
+fn choose_window(settings: &Settings, session: &StoreSession) -> Result<String, String> {
+    let prompt = "Title of window to maximize: ";
+    if input("Launch the application now to pick its window? (y/n): ")? != "y" {
+        return input(prompt);
+    }
+    let before = window_titles();
+    spit_and_log("Launching file...");
+    let launched = session
+        .get_ica_file(settings)
+        .and_then(|f| launch_file(&f, &settings.launcher, false));
+    if let Err(e) = launched {
+        spit_and_log(&format!("Error: {}\r\n\r\nFailed to launch file.", e));
+        return input(prompt);
+    }
+    input("Press Enter once the application's window has opened...")?;
+    let titles = window_titles();
+    let opened: Vec<String> = titles
+        .iter()
+        .filter(|t| !before.contains(t))
+        .cloned()
+        .collect();
+    match (opened.is_empty(), titles.is_empty()) {
+        (false, _) => {
+            spit_and_log("Windows opened since launching:");
+            choose("Window to maximize (number or title): ", &opened)
+        }
+        (true, false) => {
+            spit_and_log("No new windows found. Open windows:");
+            choose("Window to maximize (number or title): ", &titles)
+        }
+        (true, true) => input(prompt),
+    }
+}
+
+/// Show numbered options and read a choice
+/// - Returns the option for a number in range, otherwise the text entered
+/// - Returns an error String if the input ends
+fn choose(prompt: &str, options: &[String]) -> Result<String, String> {
+    for (number, option) in options.iter().enumerate() {
+        spit(format!("  {}. {}", number + 1, option));
+    }
+    let choice = input(prompt)?;
+    match choice.parse::<usize>() {
+        Ok(n) if (1..=options.len()).contains(&n) => Ok(options[n - 1].clone()),
+        _ => Ok(choice),
+    }
+}
+
 /// Load Settings from file
 /// - If no file is found, creates new Settings via create_settings
+/// - If the file is unreadable, empty, encrypted with another key or corrupted, replaces it
 pub fn get_settings() -> Result<Settings, String> {
-    // Check if settings file exists
-    // - If not, create new settings
-    match Path::new(&SETTINGS_FILE).exists() {
-        true => (),
-        false => {
-            return create_settings(
-                "No settings file was found in the current directory.\r\nLet's create one!\r\n\r\n",
-            );
-        }
+    let problem = match load_settings() {
+        Ok(s) => return Ok(s),
+        // Never replace settings from a newer version; they are still good for that version
+        Err(e) if e.starts_with(NEWER_SCHEMA) => return Err(e),
+        Err(e) => e,
     };
+    // Without a terminal, report the problem rather than prompting into nothing
+    if !is_interactive() {
+        return Err(format!("{}\r\n{}", problem, CANNOT_PROMPT));
+    }
+    match settings_path().exists() {
+        false => create_settings(&format!("{}\r\nLet's create one!\r\n\r\n", problem)),
+        true => create_settings(&format!("{}\r\nLet's make a new one!\r\n\r\n", problem)),
+    }
+}
+
+/// Load every profile from an existing settings file without prompting
+/// - First moves settings left in the working directory by earlier versions (see migrate_legacy_settings)
+/// - Restores the backup if the settings file is damaged (i.e. truncated by a power cut)
+/// - Upgrades older layouts and writes the upgrade back
+/// - Returns an error String describing why the file could not be used
+pub fn load_store() -> Result<SettingsStore, String> {
+    let mut path = settings_path();
+    if !path.exists() && SETTINGS_PATH.get().is_none() {
+        migrate_legacy_settings(&path);
+        path = settings_path();
+    }
+    if !path.exists() && SETTINGS_PATH.get().is_none() {
+        let searched: Vec<String> = settings_candidates()
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(format!(
+            "No settings file was found. Searched: {}",
+            searched.join(", ")
+        ));
+    }
+    if !path.exists() {
+        return Err(format!("No settings file was found at {}.", path.display()));
+    }
+    migrate_secrets_name(&path);
+    let (store, version, combined) = match read_store(&path, &secrets_path()) {
+        Ok(r) => r,
+        Err(e) if e.starts_with(NEWER_SCHEMA) => return Err(e),
+        Err(e) => restore_backup(&e)?,
+    };
+    log_to_file(&format!("Using settings file {}", path.display()));
+    // Write the upgrade back so it only happens once (best effort, i.e. read-only /etc)
+    if version < SCHEMA_VERSION || combined {
+        match save_store(&store) {
+            Ok(_) if version < SCHEMA_VERSION => spit_and_log(&format!(
+                "Upgraded settings file from schema version {} to {}.",
+                version, SCHEMA_VERSION
+            )),
+            Ok(_) => (),
+            Err(e) => {
+                spit_and_log(&format!(
+                    "Error: {}\r\n\r\nFailed to save upgraded settings. They will be upgraded again next time.",
+                    e
+                ));
+                return Ok(store);
+            }
+        }
+        if combined {
+            spit_and_log(&format!(
+                "Secrets moved out of the settings file into {}.",
+                secrets_path().display()
+            ));
+        }
+    }
+    Ok(store)
+}
+
+/// Read every profile from a settings file and its secrets file
+/// - Returns the store, the schema version it was stored as, and whether it was an older
+///   file encrypted as a whole (with no separate secrets file)
+fn read_store(path: &Path, secrets_path: &Path) -> Result<(SettingsStore, u32, bool), String> {
     // Attempt to read existing settings file
-    // - If file is empty, corrupted, or unreadable, create new settings
     // TODO: Is this creating a race condition when file disappears or no perm to read?
-    let f =
-        match fs::read_to_string(&SETTINGS_FILE) {
-            Ok(f) => f,
-            Err(_) => return create_settings(
-                "A settings file was found, but it's unreadable.\r\nLet's make a new one!\r\n\r\n",
-            ),
-        };
+    let f = match fs::read_to_string(path) {
+        Ok(f) => f,
+        Err(_) => return Err("A settings file was found, but it's unreadable.".to_string()),
+    };
     // Check if file is empty (prevents unnecessary decryption and deserialization)
-    // - If so, create new settings
     if f.is_empty() {
-        return create_settings(
-            "A settings file was found, but it's empty.\r\nLet's make a new one!\r\n\r\n",
-        );
-    }
-    // Attempt to decrypt settings file
-    // - If encryption key doesn't match (or other errors occur), create new settings
-    let d =
-        match decrypt_string(f) {
-            Ok(d) => d,
-            Err(_) => return create_settings(
-                "A settings file was found, but the encryption key doesn't match.\r\nLet's make a new one!\r\n\r\n",
-            ),
+        return Err("A settings file was found, but it's empty.".to_string());
+    }
+    // Readable config with secrets alongside, or an older file encrypted as a whole
+    let combined = !f.trim_start().starts_with('{');
+    let mut value: Value = match combined {
+        true => match from_str(&decrypt_file(f, "settings")?) {
+            Ok(v) => v,
+            Err(_) => return Err("A settings file was found, but it's corrupted.".to_string()),
+        },
+        false => match from_str(&f) {
+            Ok(v) => v,
+            Err(_) => return Err("A settings file was found, but it's corrupted.".to_string()),
+        },
+    };
+    if !combined {
+        merge_secrets(&mut value, read_secrets(secrets_path)?);
+    }
+    // Upgrade older layouts, then deserialize
+    // - Fails if data format doesn't match SettingsStore struct
+    let version = migrate(&mut value)?;
+    match from_value(value) {
+        Ok(s) => Ok((s, version, combined)),
+        Err(_) => Err("A settings file was found, but it's corrupted.".to_string()),
+    }
+}
+
+/// Restore the settings from the backup kept by save_store
+/// - The backup is a copy of the last save, so only changes made to the files since by other
+///   means (i.e. by hand or a config management tool) are lost, and the message says so
+/// - Accepts the reason the settings file could not be used
+/// - Copies the backup over the damaged files, so the next load needn't restore again
+/// - Sets aside a file the backup has no copy of, since it may be the damaged one
+/// - Returns the reason itself if there is no usable backup
+fn restore_backup(problem: &str) -> Result<(SettingsStore, u32, bool), String> {
+    let files = [
+        (backup_path(&settings_path()), settings_path()),
+        (backup_path(&secrets_path()), secrets_path()),
+    ];
+    let restored = match read_store(&files[0].0, &files[1].0) {
+        Ok(r) => r,
+        Err(_) => return Err(problem.to_string()),
+    };
+    for (backup, path) in &files {
+        let restored = match fs::read(backup) {
+            Ok(contents) => write_atomic(path, &contents),
+            // The backup had no secrets file, so the current one may be the damaged file
+            Err(_) => set_aside(path),
         };
-    // Attempt to deserialize settings file
-    // - If file is corrupted or data format doesn't match Settings struct, create new settings
-    match from_str(&d) {
-        Ok(s) => Ok(s),
-        Err(_) => create_settings(
-            "A settings file was found, but it's corrupted.\r\nLet's make a new one!\r\n\r\n",
-        ),
+        if let Err(e) = restored {
+            spit_and_log(&format!("Error: {}\r\n\r\nFailed to restore settings.", e));
+        }
+    }
+    spit_and_log(&format!(
+        "{} Restored the settings as this app last saved them, from {}. Changes made to {} since by other means are lost.",
+        problem,
+        files[0].0.display(),
+        files[0].1.display()
+    ));
+    Ok(restored)
+}
+
+/// Read the encrypted secrets stored beside a settings file
+/// - A missing secrets file means no secrets (i.e. a config written by a management tool)
+/// - Returns the secrets as JSON, i.e. {"profiles": {"default": {"passwd": "..."}}}
+fn read_secrets(path: &Path) -> Result<Value, String> {
+    let f = match fs::read_to_string(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Value::Null),
+        Err(_) => return Err("A secrets file was found, but it's unreadable.".to_string()),
+    };
+    if f.is_empty() {
+        return Err("A secrets file was found, but it's empty.".to_string());
+    }
+    match from_str(&decrypt_file(f, "secrets")?) {
+        Ok(v) => Ok(v),
+        Err(_) => Err("A secrets file was found, but it's corrupted.".to_string()),
+    }
+}
+
+/// Decrypt a settings or secrets file with this build's key
+/// - Accepts the file's contents and what kind of file it is, for the error
+/// - A file which isn't encrypted data at all is reported as corrupted, not as another key's
+fn decrypt_file(contents: String, kind: &str) -> Result<String, String> {
+    if hex::decode(&contents).is_err() {
+        return Err(format!("A {} file was found, but it's corrupted.", kind));
+    }
+    match decrypt_string(contents) {
+        Ok(d) => Ok(d),
+        Err(_) => Err(format!(
+            "A {} file was found, but the encryption key doesn't match.",
+            kind
+        )),
+    }
+}
+
+/// Put each profile's secrets back into the settings, matched by profile name
+/// - Secrets for profiles missing from the settings are dropped
+fn merge_secrets(settings: &mut Value, secrets: Value) {
+    let secrets = match secrets {
+        Value::Object(mut s) => match s.remove("profiles") {
+            Some(Value::Object(p)) => p,
+            _ => return,
+        },
+        _ => return,
+    };
+    for (name, profile_secrets) in secrets {
+        if let (Some(profile), Value::Object(fields)) = (
+            settings["profiles"]
+                .get_mut(&name)
+                .and_then(|p| p.as_object_mut()),
+            profile_secrets,
+        ) {
+            profile.extend(fields);
+        }
+    }
+}
+
+/// Take each profile's secrets out of the settings, keyed by profile name
+/// - Returns the secrets as JSON, i.e. {"profiles": {"default": {"passwd": "..."}}}
+fn split_secrets(settings: &mut Value) -> Value {
+    let mut secrets = Map::new();
+    if let Some(profiles) = settings["profiles"].as_object_mut() {
+        for (name, profile) in profiles.iter_mut() {
+            let profile = match profile.as_object_mut() {
+                Some(p) => p,
+                None => continue,
+            };
+            let fields: Map<String, Value> = SECRET_FIELDS
+                .iter()
+                .filter_map(|f| Some((f.to_string(), profile.remove(*f)?)))
+                .collect();
+            secrets.insert(name.clone(), Value::Object(fields));
+        }
+    }
+    let mut wrapper = Map::new();
+    wrapper.insert("profiles".to_string(), Value::Object(secrets));
+    Value::Object(wrapper)
+}
+
+/// Load the active profile's Settings without prompting
+/// - Returns an error String describing why the file or profile could not be used
+pub fn load_settings() -> Result<Settings, String> {
+    let mut store = load_store()?;
+    let name = store.active_profile();
+    match store.profiles.remove(&name) {
+        Some(s) => Ok(s),
+        None => Err(format!(
+            "The settings file has no profile named {}. Profiles: {}",
+            name,
+            store
+                .profiles
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Fields which can be changed with set_field, as named on the command line
+const SETTABLE_FIELDS: [&str; 16] = [
+    "base_uri",
+    "application_name",
+    "login",
+    "passwd",
+    "target",
+    "maximization_active",
+    "auth_method",
+    "session_cleanup",
+    "launch_arguments",
+    "client_name",
+    "client_processes",
+    "launcher",
+    "launcher_directory",
+    "launcher_environment.<NAME>",
+    "nfactor.<field ID>",
+    "credential_source.<passwd or nfactor.<field ID>>",
+];
+
+/// Check if a field holds a secret, so it is prompted for without echo
+pub fn is_secret_field(field: &str) -> bool {
+    field == "passwd" || field.starts_with("nfactor.")
+}
+
+/// Change a single field of Settings
+/// - Only the changed field is validated
+/// - Values are trimmed, except for secrets
+/// - nfactor.<ID> and launcher_environment.<NAME> set one entry; a blank value removes it
+/// - credential_source.<field> takes env:<NAME>, file:<path> or command:<command line>
+/// - Returns an error String if the field is unknown or the value is invalid
+pub fn set_field(settings: &mut Settings, field: &str, value: &str) -> Result<(), String> {
+    // Secrets are kept as given, as spaces may be part of a password
+    let value = match is_secret_field(field) {
+        true => value,
+        false => value.trim(),
+    };
+    if let Some(secret) = field.strip_prefix("credential_source.") {
+        if !is_secret_field(secret) {
+            return Err("Credential sources apply to passwd and nfactor.<field ID>".to_string());
+        }
+        if value.is_empty() {
+            settings.credential_sources.remove(secret);
+            return Ok(());
+        }
+        let source = match value.split_once(':') {
+            Some(("env", name)) if !name.is_empty() => {
+                CredentialSource::Environment(name.to_string())
+            }
+            Some(("file", path)) if !path.is_empty() => CredentialSource::File(path.to_string()),
+            Some(("command", line)) if !line.trim().is_empty() => {
+                CredentialSource::Command(line.to_string())
+            }
+            _ => {
+                return Err("Expected env:<NAME>, file:<path> or command:<command line>".to_string())
+            }
+        };
+        settings
+            .credential_sources
+            .insert(secret.to_string(), source);
+        return Ok(());
+    }
+    if let Some(id) = field.strip_prefix("nfactor.") {
+        match value.is_empty() {
+            true => settings.nfactor_fields.remove(id),
+            false => settings
+                .nfactor_fields
+                .insert(id.to_string(), value.to_string()),
+        };
+        return Ok(());
+    }
+    if let Some(name) = field.strip_prefix("launcher_environment.") {
+        let command = match &mut settings.launcher {
+            Launcher::Command(c) => c,
+            Launcher::Default => return Err("Set a launcher command first".to_string()),
+        };
+        match value.is_empty() {
+            true => command.environment.remove(name),
+            false => command
+                .environment
+                .insert(name.to_string(), value.to_string()),
+        };
+        return Ok(());
+    }
+    match field {
+        "base_uri" => {
+            let uri = match Url::parse(value) {
+                Ok(u) => u,
+                Err(e) => return Err(format!("Invalid URI: {:?}", e)),
+            };
+            if uri.scheme() != "https" {
+                return Err("URI must use HTTPS.".to_string());
+            }
+            settings.base_uri = value.to_string();
+        }
+        "application_name" => {
+            if value.is_empty() {
+                return Err("Application name cannot be blank".to_string());
+            }
+            settings.application_name = value.to_string();
+        }
+        "login" => settings.login = value.to_string(),
+        "passwd" => settings.passwd = value.to_string(),
+        "target" => settings.target = value.to_string(),
+        "maximization_active" => {
+            settings.maximization_active = match value.to_lowercase().as_str() {
+                "y" | "yes" | "true" => true,
+                "n" | "no" | "false" => false,
+                _ => return Err("Expected y or n".to_string()),
+            }
+        }
+        "auth_method" => {
+            settings.auth_method = match value.to_lowercase().as_str() {
+                "explicitforms" | "password" => AuthMethod::ExplicitForms,
+                "negotiate" | "kerberos" => AuthMethod::Negotiate,
+                "anonymous" => AuthMethod::Anonymous,
+                _ => return Err("Expected explicitforms, negotiate or anonymous".to_string()),
+            }
+        }
+        "session_cleanup" => {
+            settings.session_cleanup = match value.to_lowercase().as_str() {
+                "" | "n" | "none" => SessionCleanup::None,
+                "d" | "disconnect" => SessionCleanup::Disconnect,
+                "l" | "logoff" => SessionCleanup::Logoff,
+                _ => return Err("Expected none, disconnect or logoff".to_string()),
+            }
+        }
+        "launch_arguments" => settings.launch_arguments = value.to_string(),
+        "client_name" => {
+            settings.client_name = match value.is_empty() {
+                true => default_client_name(),
+                false => value.to_string(),
+            }
+        }
+        "client_processes" => {
+            settings.client_processes = value
+                .split(',')
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty())
+                .collect()
+        }
+        "launcher" => {
+            settings.launcher = match (value.is_empty(), &settings.launcher) {
+                (true, _) => Launcher::Default,
+                (false, _) if !value.contains("{ica}") => {
+                    return Err("Launcher command must contain {ica}".to_string())
+                }
+                (false, Launcher::Command(c)) => Launcher::Command(LaunchCommand {
+                    template: value.to_string(),
+                    ..c.clone()
+                }),
+                (false, Launcher::Default) => Launcher::Command(LaunchCommand {
+                    template: value.to_string(),
+                    ..LaunchCommand::default()
+                }),
+            }
+        }
+        "launcher_directory" => match &mut settings.launcher {
+            Launcher::Command(c) => c.working_directory = value.to_string(),
+            Launcher::Default => return Err("Set a launcher command first".to_string()),
+        },
+        _ => {
+            return Err(format!(
+                "Unknown setting: {}\r\n\r\nSettings that can be changed: {}",
+                field,
+                SETTABLE_FIELDS.join(", ")
+            ))
+        }
     }
+    Ok(())
 }
 
-/// Save Settings to file
-/// - Encrypts Settings before saving
+/// Copy a profile to a new name
+/// - Returns an error String if the source is missing or the target already exists
+pub fn copy_profile(from: &str, to: &str) -> Result<(), String> {
+    let mut store = load_store()?;
+    if store.profiles.contains_key(to) {
+        return Err(format!("Profile {} already exists", to));
+    }
+    let settings = match store.profiles.get(from) {
+        Some(s) => s.clone(),
+        None => return Err(format!("No profile named {}", from)),
+    };
+    store.profiles.insert(to.to_string(), settings);
+    save_store(&store)
+}
+
+/// Delete a profile
+/// - The default profile can only be deleted once another is made the default
+/// - Returns an error String if the profile is missing or is the default
+pub fn delete_profile(name: &str) -> Result<(), String> {
+    let mut store = load_store()?;
+    if name == store.default_profile && store.profiles.len() > 1 {
+        return Err(format!(
+            "Profile {} is the default. Make another profile the default first",
+            name
+        ));
+    }
+    if store.profiles.remove(name).is_none() {
+        return Err(format!("No profile named {}", name));
+    }
+    save_store(&store)
+}
+
+/// Mark a profile as the default, used when no --profile is given
+/// - Returns an error String if the profile is missing
+pub fn set_default_profile(name: &str) -> Result<(), String> {
+    let mut store = load_store()?;
+    if !store.profiles.contains_key(name) {
+        return Err(format!("No profile named {}", name));
+    }
+    store.default_profile = name.to_string();
+    save_store(&store)
+}
+
+/// Create new Settings from user input, replacing any existing file
+pub fn setup_settings() -> Result<Settings, String> {
+    create_settings("Let's set up your settings!\r\n\r\n")
+}
+
+/// Delete the settings file, its secrets file and their backups
+/// - Returns Ok if there was no file to delete
+pub fn reset_settings() -> Result<(), String> {
+    for path in [
+        settings_path(),
+        secrets_path(),
+        backup_path(&settings_path()),
+        backup_path(&secrets_path()),
+    ] {
+        match fs::remove_file(&path) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(format!("Failed to delete {}: {:?}", path.display(), e)),
+        }
+    }
+    Ok(())
+}
+
+/// Save Settings to file as the active profile
+/// - Keeps the other profiles in the file
+/// - Starts a new file only if there is none; an unusable file is an error, so no profile is lost
+/// - The first profile saved becomes the default
 /// - Returns Result<(), String>
 pub fn save_settings(settings: &Settings) -> Result<(), String> {
-    let serialized = match to_string(&settings) {
+    let mut store = match load_store() {
+        Ok(s) => s,
+        Err(_) if !settings_path().exists() => SettingsStore::default(),
+        Err(e) => return Err(e),
+    };
+    let name = store.active_profile();
+    if store.profiles.is_empty() {
+        store.default_profile = name.clone();
+    }
+    store.profiles.insert(name, settings.clone());
+    save_store(&store)
+}
+
+/// Save every profile to file
+/// - Writes the settings as readable JSON, without secrets
+/// - Files are written atomically and readable by their owner only
+/// - Keeps a copy of what was saved as .bak files, restored if the files are later damaged
+/// - Encrypts the secrets into the secrets file beside it, keyed by profile name
+/// - Creates the settings directory if needed
+/// - Returns Result<(), String>
+pub fn save_store(store: &SettingsStore) -> Result<(), String> {
+    let mut value = match to_value(store) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Failed to serialize settings: {:?}", e)),
+    };
+    let secrets = split_secrets(&mut value);
+    let config = match to_string_pretty(&value) {
         Ok(s) => s,
         Err(e) => return Err(format!("Failed to serialize settings: {:?}", e)),
     };
-    let encrypted = match encrypt_string(&serialized) {
+    let secrets = match to_string(&secrets) {
+        Ok(s) => s,
+        Err(e) => return Err(format!("Failed to serialize secrets: {:?}", e)),
+    };
+    let encrypted = match encrypt_string(&secrets) {
         Ok(e) => e,
-        Err(e) => return Err(format!("Failed to encrypt settings: {:?}", e)),
+        Err(e) => return Err(format!("Failed to encrypt secrets: {:?}", e)),
+    };
+    let (path, secrets_path) = (settings_path(), secrets_path());
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        if let Err(e) = fs::create_dir_all(dir) {
+            return Err(format!("Failed to create settings directory: {:?}", e));
+        }
+    }
+    // Secrets first, so the settings never name a profile whose secrets are missing
+    write_atomic(&secrets_path, encrypted.as_bytes())?;
+    write_atomic(&path, config.as_bytes())?;
+    // Back up what was just saved, so a restore loses nothing this app saved; the save
+    // itself has succeeded, so a failed backup is only reported
+    for (p, contents) in [(&secrets_path, &encrypted), (&path, &config)] {
+        if let Err(e) = write_atomic(&backup_path(p), contents.as_bytes()) {
+            spit_and_log(&format!("Error: {}\r\n\r\nFailed to back up settings.", e));
+        }
+    }
+    Ok(())
+}
+
+/// Make a file readable and writable by its owner only on Windows, as mode 0o600 does elsewhere
+/// - Replaces the inherited permissions with a single entry giving the current user full control
+/// - Returns an error String if the current user can't be found or the permissions can't be set
+#[cfg(target_os = "windows")]
+fn restrict_to_owner(path: &Path) -> Result<(), String> {
+    let mut token: HANDLE = null_mut();
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+        return Err(format!(
+            "Failed to open the process token: {:?}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    // Ask for the size first; a u64 buffer keeps TOKEN_USER aligned
+    let mut length = 0;
+    unsafe { GetTokenInformation(token, TokenUser, null_mut(), 0, &mut length) };
+    let mut user = vec![0u64; (length as usize).div_ceil(8)];
+    let found = unsafe {
+        GetTokenInformation(
+            token,
+            TokenUser,
+            user.as_mut_ptr().cast(),
+            length,
+            &mut length,
+        )
+    };
+    unsafe { CloseHandle(token) };
+    if found == 0 {
+        return Err(format!(
+            "Failed to get the current user: {:?}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    let sid = unsafe { (*user.as_ptr().cast::<TOKEN_USER>()).User.Sid };
+    // The ACL holds one ACE, whose SidStart field is where the SID begins
+    let acl_length = size_of::<ACL>() + size_of::<ACCESS_ALLOWED_ACE>() - size_of::<u32>()
+        + unsafe { GetLengthSid(sid) } as usize;
+    let mut acl_buffer = vec![0u64; acl_length.div_ceil(8)];
+    let acl = acl_buffer.as_mut_ptr().cast::<ACL>();
+    if unsafe { InitializeAcl(acl, acl_length as u32, ACL_REVISION) } == 0
+        || unsafe { AddAccessAllowedAce(acl, ACL_REVISION, GENERIC_ALL, sid) } == 0
+    {
+        return Err(format!(
+            "Failed to build permissions for {}: {:?}",
+            path.display(),
+            std::io::Error::last_os_error()
+        ));
+    }
+    let name: Vec<u16> = path.as_os_str().encode_wide().chain(once(0)).collect();
+    // A protected DACL doesn't inherit the directory's permissions
+    let result = unsafe {
+        SetNamedSecurityInfoW(
+            name.as_ptr(),
+            SE_FILE_OBJECT,
+            DACL_SECURITY_INFORMATION | PROTECTED_DACL_SECURITY_INFORMATION,
+            null_mut(),
+            null_mut(),
+            acl,
+            null_mut(),
+        )
     };
-    match fs::write(SETTINGS_FILE, encrypted) {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!("Failed to write settings file: {:?}", e)),
+    match result {
+        ERROR_SUCCESS => Ok(()),
+        e => Err(format!(
+            "Failed to restrict {} to its owner: {:?}",
+            path.display(),
+            std::io::Error::from_raw_os_error(e as i32)
+        )),
     }
 }
 
-/// Launch file in default application
+/// Get the backup path for a settings or secrets file, i.e. settings.txt.bak
+fn backup_path(path: &Path) -> PathBuf {
+    with_suffix(path, BACKUP_SUFFIX)
+}
+
+/// Append a suffix to the whole file name, i.e. settings.txt and .bak make settings.txt.bak
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Write a file atomically, readable and writable by its owner only
+/// - Owner only means mode 0o600 on Unix and a protected DACL on Windows (see restrict_to_owner)
+/// - Writes a temporary file beside it, flushes it to disk, then renames it over the file
+/// - A power cut leaves either the old or the new file, never a truncated one
 /// - Returns Result<(), String>
-pub fn launch_file(file_name: &str) -> Result<(), String> {
-    match open::that(file_name) {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!("Failed to launch file: {:?}", e)),
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let temp = with_suffix(path, TEMP_SUFFIX);
+    // A leftover temporary file may have wider permissions; start afresh
+    let _ = fs::remove_file(&temp);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = match options.open(&temp) {
+        Ok(f) => f,
+        Err(e) => return Err(format!("Failed to create {}: {:?}", temp.display(), e)),
+    };
+    #[cfg(target_os = "windows")]
+    if let Err(e) = restrict_to_owner(&temp) {
+        drop(file);
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    if let Err(e) = file.write_all(contents).and_then(|_| file.sync_all()) {
+        let _ = fs::remove_file(&temp);
+        return Err(format!("Failed to write {}: {:?}", temp.display(), e));
+    }
+    drop(file);
+    if let Err(e) = fs::rename(&temp, path) {
+        let _ = fs::remove_file(&temp);
+        return Err(format!("Failed to replace {}: {:?}", path.display(), e));
+    }
+    // Flush the directory too, so the rename itself survives a power cut
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = match dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir,
+        };
+        if let Ok(d) = fs::File::open(dir) {
+            let _ = d.sync_all();
+        }
     }
+    Ok(())
 }